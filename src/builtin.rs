@@ -0,0 +1,137 @@
+//! Built-in functions, called as `name(args...)`.
+
+use crate::expr::IntType;
+use crate::traits::Int;
+use crate::wide;
+
+/// A built-in function. Arguments all have the same type as the result (see
+/// `Expr::typ()`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString, strum::IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+pub enum Builtin {
+    /// Number of set bits.
+    Popcnt,
+    /// Number of leading zeros.
+    Clz,
+    /// Number of trailing zeros.
+    Ctz,
+    /// Number of leading bits, after the top one, equal to the top one.
+    Cls,
+    /// Bytes in reverse order.
+    Bswap,
+    /// Bits in reverse order.
+    Bitrev,
+    /// 1 if the number of set bits is odd, else 0.
+    Parity,
+    /// High half of the full product, with both operands signed.
+    Mulh,
+    /// High half of the full product, with both operands unsigned.
+    Mulhu,
+    /// High half of the full product, with the left operand signed and the
+    /// right unsigned.
+    Mulhsu,
+}
+
+impl Builtin {
+    pub fn name(self) -> &'static str {
+        self.into()
+    }
+
+    /// Number of arguments.
+    pub fn arity(self) -> usize {
+        use Builtin::*;
+        match self {
+            Popcnt | Clz | Ctz | Cls | Bswap | Bitrev | Parity => 1,
+            Mulh | Mulhu | Mulhsu => 2,
+        }
+    }
+
+    /// Applies the function to `args`, of type `typ`, held in `T` (see
+    /// `with_int_type!`). Returns None if the function doesn't apply to `typ`
+    /// (i.e., `bswap` of a type that isn't a whole number of bytes).
+    pub fn eval<T: Int>(self, typ: IntType, args: &[T]) -> Option<T> {
+        assert_eq!(args.len(), self.arity(), "Wrong number of arguments to {}", self.name());
+        let val = args[0];
+
+        // The value's bits, moved to the top of the container type, so the
+        // `PrimInt` methods see exactly the type's bits, followed by zeros.
+        let top = val << typ.excess_bits::<T>();
+        let count = |n: u32| T::from_u32(n).unwrap();
+
+        use Builtin::*;
+        Some(match self {
+            Popcnt => count(top.count_ones()),
+            Clz => count(top.leading_zeros().min(typ.bits)),
+            Ctz => count(val.trailing_zeros().min(typ.bits)),
+            Cls => {
+                let top = if top.leading_zeros() == 0 { !top } else { top };
+                count(top.leading_zeros().min(typ.bits) - 1)
+            }
+            Bswap if !typ.bits.is_multiple_of(8) => return None,
+            Bswap => typ.wrap(top.swap_bytes()),
+            Bitrev => typ.wrap(top.reverse_bits()),
+            Parity => count(top.count_ones() % 2),
+            Mulh | Mulhu | Mulhsu => {
+                let (l_signed, r_signed) = match self {
+                    Mulh => (true, true),
+                    Mulhu => (false, false),
+                    _ => (true, false),
+                };
+                let (l, r) = (typ.pattern(val), typ.pattern(args[1]));
+                let (hi, _) = wide::mul(l, r, typ.bits, l_signed, r_signed);
+                typ.pattern_value(hi)
+            }
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::with_int_type;
+
+    fn eval(f: Builtin, typ: &str, args: &[i128]) -> Option<i128> {
+        let typ: IntType = typ.parse().unwrap();
+        with_int_type!(typ, T => {
+            let args: Vec<T> = args.iter().map(|a| typ.wrap(T::from_i128_as(*a))).collect();
+            f.eval(typ, &args).map(|v| v.as_i128())
+        })
+    }
+
+    #[test]
+    fn counts() {
+        use Builtin::*;
+        assert_eq!(eval(Popcnt, "u8", &[0xf1]), Some(5));
+        assert_eq!(eval(Popcnt, "i12", &[-1]), Some(12));
+        assert_eq!(eval(Popcnt, "u128", &[-1]), Some(128));
+        assert_eq!(eval(Clz, "u8", &[0x10]), Some(3));
+        assert_eq!(eval(Clz, "u12", &[0x10]), Some(7));
+        assert_eq!(eval(Clz, "i12", &[0]), Some(12));
+        assert_eq!(eval(Clz, "i8", &[-1]), Some(0));
+        assert_eq!(eval(Ctz, "u8", &[0x10]), Some(4));
+        assert_eq!(eval(Ctz, "i12", &[0]), Some(12));
+        assert_eq!(eval(Ctz, "i64", &[i64::MIN as i128]), Some(63));
+        assert_eq!(eval(Cls, "i8", &[-1]), Some(7));
+        assert_eq!(eval(Cls, "i8", &[0]), Some(7));
+        assert_eq!(eval(Cls, "i8", &[1]), Some(6));
+        assert_eq!(eval(Cls, "u12", &[0xf00]), Some(3));
+        assert_eq!(eval(Cls, "i12", &[0x0ff]), Some(3));
+        assert_eq!(eval(Parity, "u8", &[0x7]), Some(1));
+        assert_eq!(eval(Parity, "u24", &[0x30_0000]), Some(0));
+    }
+
+    #[test]
+    fn reorder() {
+        use Builtin::*;
+        assert_eq!(eval(Bswap, "u32", &[0x12345678]), Some(0x78563412));
+        assert_eq!(eval(Bswap, "u24", &[0x123456]), Some(0x563412));
+        assert_eq!(eval(Bswap, "i16", &[0x80]), Some(-0x8000));
+        assert_eq!(eval(Bswap, "u8", &[0x12]), Some(0x12));
+        assert_eq!(eval(Bswap, "u12", &[0x123]), None);
+        assert_eq!(eval(Bitrev, "u8", &[0x01]), Some(0x80));
+        assert_eq!(eval(Bitrev, "u12", &[0x00f]), Some(0xf00));
+        assert_eq!(eval(Bitrev, "i4", &[1]), Some(-8));
+        assert_eq!(eval(Bitrev, "u128", &[1]), Some(1 << 127));
+    }
+}
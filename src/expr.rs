@@ -1,95 +1,1614 @@
-
+use crate::builtin::Builtin;
 use crate::traits::Int;
+use crate::wide;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+use std::str::FromStr;
 
+use clap::ValueEnum;
 use thiserror::Error;
 
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum Error {
-    #[error("Error parsing literal: {}", .0)]
-    LitParse(String),
+    /// A literal (`lit`, at byte offsets `span` in the input) that can't be
+    /// parsed, because of `msg`.
+    #[error("Invalid literal '{}': {}", .lit, .msg)]
+    LitParse { lit: String, msg: String, span: Range<usize> },
+
+    #[error("{}", .0)]
+    InvalidType(String),
+
+    /// A call to the built-in `name` (at byte offsets `span` in the input)
+    /// with the wrong number of arguments.
+    #[error("'{}' takes {} argument(s), but {} were given", .name, .arity, .given)]
+    Arity { name: String, arity: usize, given: usize, span: Range<usize> },
+
+    #[error("'{}' is a built-in function", .0)]
+    BuiltinName(String),
+
+    #[error("Parameter '{}' given more than once", .0)]
+    DuplicateParam(String),
+
+    #[error("Can't assign to '{}'", .0)]
+    AssignTarget(String),
+}
+
+impl Error {
+    /// Location of the error in the input, if it's for a particular part of it.
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            Error::LitParse { span, .. } | Error::Arity { span, .. } => Some(span.clone()),
+            _ => None,
+        }
+    }
+}
+
+
+/// An integer (or character) literal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lit {
+    pub val: u128,
+    /// Whether the literal was written in decimal.
+    pub decimal: bool,
+    /// Type given by a suffix, if any.
+    pub typ: Option<IntType>,
+}
+
+impl Lit {
+    /// Whether the literal gives a bit pattern, rather than a number; i.e.,
+    /// whether, for a signed type, it's reinterpreted as two's complement.
+    /// Only non-decimal literals do, unless `opts.strict_literals`.
+    fn is_pattern(&self, opts: &Options) -> bool {
+        !self.decimal && !opts.strict_literals
+    }
+
+    /// The literal's value at type `typ`, held in `T` (see `with_int_type!`).
+    fn eval<T: Int>(&self, opts: &Options, typ: IntType) -> Result<T, EvalErr> {
+        let invalid = || EvalErr::Invalid(self.to_string());
+        if self.is_pattern(opts) {
+            if self.val > IntType::new(false, typ.bits).max() {
+                return Err(invalid());
+            }
+            Ok(typ.pattern_value(self.val))
+        } else {
+            T::from_u128(self.val).filter(|v| typ.wrap(*v) == *v).ok_or_else(invalid)
+        }
+    }
+}
+
+impl fmt::Display for Lit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.val)?;
+        match self.typ {
+            Some(typ) => write!(f, "{typ}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses a literal's type suffix: Rust-style (e.g., `u8` or `i16`), or
+/// C-style, taking `int` to be 32 bits and `long` 64, as on LP64 platforms.
+fn parse_suffix(suffix: &str) -> Result<IntType, String> {
+    if let Ok(typ) = suffix.parse() {
+        return Ok(typ);
+    }
+    match suffix.to_ascii_uppercase().as_str() {
+        "U" => Ok(IntType::U32),
+        "L" | "LL" => Ok(IntType::I64),
+        "UL" | "LU" | "ULL" | "LLU" => Ok(IntType::U64),
+        _ => Err(format!("invalid suffix '{suffix}'")),
+    }
+}
+
+/// Parses the base of a literal in any radix, from 2 to 36.
+fn parse_radix(radix: &str) -> Result<u32, String> {
+    radix.parse().ok()
+        .filter(|radix| (2..=36).contains(radix))
+        .ok_or_else(|| format!("invalid base '{radix}' (expected 2 to 36)"))
+}
+
+/// Parses an integer literal: decimal, or hexadecimal, octal, or binary with a
+/// `0x`, `0o`, or `0b` prefix, or in any radix from 2 to 36, as `radix#digits`
+/// or `0rradix_digits`. Digits may be separated by any number of `_`, and
+/// followed by a type suffix (see `parse_suffix()`).
+pub fn parse_lit(lit: &str) -> Result<Lit, String> {
+    let (radix, digits) = match lit.get(..2) {
+        Some("0x") => (16, &lit[2..]),
+        Some("0o") => (8, &lit[2..]),
+        Some("0b") => (2, &lit[2..]),
+        Some("0r") => {
+            let (radix, digits) = lit[2..].split_once('_').ok_or("expected '_' after the base")?;
+            (parse_radix(radix)?, digits)
+        }
+        _ => match lit.split_once('#') {
+            Some((radix, digits)) => (parse_radix(radix)?, digits),
+            None => (10, lit),
+        },
+    };
+    // Suffixes start with a letter that isn't a digit in the base (so in bases
+    // above 18, they may not be possible).
+    let suffix_start = |ch: char| "uUiIlL".contains(ch) && !ch.is_digit(radix);
+    let (digits, typ) = match digits.find(suffix_start) {
+        Some(i) => (&digits[..i], Some(parse_suffix(&digits[i..])?)),
+        None => (digits, None),
+    };
+
+    let mut val = None;
+    for ch in digits.chars().filter(|ch| *ch != '_') {
+        let digit = ch.to_digit(radix).ok_or_else(|| format!("invalid digit '{ch}' for base {radix}"))?;
+        val = Some(val.unwrap_or(0u128)
+            .checked_mul(radix as u128)
+            .and_then(|v| v.checked_add(digit as u128))
+            .ok_or("too large for 128 bits")?);
+    }
+    let val = val.ok_or("no digits")?;
+    Ok(Lit { val, decimal: radix == 10, typ })
 }
 
+/// A character in a character or byte string literal.
+enum Unit {
+    /// Given by a `\x` escape.
+    Byte(u8),
+    Char(char),
+}
+
+/// The characters in `lit`, the contents of a character or byte string literal
+/// (i.e., without quotes), with escapes replaced. The escapes are as in Rust:
+/// `\n`, `\r`, `\t`, `\0`, `\\`, `\'`, `\"`, `\xHH`, and `\u{H...}`.
+fn unescape(lit: &str) -> Result<Vec<Unit>, String> {
+    let mut units = vec![];
+    let mut chars = lit.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            units.push(Unit::Char(ch));
+            continue;
+        }
+
+        let esc = chars.next().ok_or("unterminated escape")?;
+        units.push(match esc {
+            'n' => Unit::Char('\n'),
+            'r' => Unit::Char('\r'),
+            't' => Unit::Char('\t'),
+            '0' => Unit::Char('\0'),
+            '\\' | '\'' | '"' => Unit::Char(esc),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 => Unit::Byte(byte),
+                    _ => return Err(format!("invalid escape '\\x{hex}'")),
+                }
+            }
+            'u' => {
+                let rest = chars.as_str();
+                let code = rest.strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .map(|(code, _)| code)
+                    .ok_or("invalid escape '\\u'")?;
+                let ch = u32::from_str_radix(&code.replace('_', ""), 16).ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid escape '\\u{{{code}}}'"))?;
+                chars.nth(code.chars().count() + 1);
+                Unit::Char(ch)
+            }
+            _ => return Err(format!("unknown escape '\\{esc}'")),
+        });
+    }
+    Ok(units)
+}
 
+/// Parses a character literal, including the quotes, giving its code point (or
+/// for a `\x` escape, the byte), as a bit pattern.
+pub fn parse_char(lit: &str) -> Result<Lit, String> {
+    match unescape(&lit[1..lit.len() - 1])?.as_slice() {
+        [Unit::Byte(byte)] => Ok(Lit { val: *byte as u128, decimal: false, typ: None }),
+        [Unit::Char(ch)] => Ok(Lit { val: *ch as u128, decimal: false, typ: None }),
+        _ => Err("must be exactly one character".to_owned()),
+    }
+}
 
-#[derive(Debug, Clone, Copy, Error)]
+/// Parses a byte string literal, including the `b` and quotes, giving its
+/// bytes. Characters other than `\x` escapes are encoded as UTF-8.
+pub fn parse_bytes(lit: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    for unit in unescape(&lit[2..lit.len() - 1])? {
+        match unit {
+            Unit::Byte(byte) => bytes.push(byte),
+            Unit::Char(ch) => bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Ok(bytes)
+}
+
+
+#[derive(Debug, Clone, Error)]
 pub enum EvalErr{
     #[error("Literal '{}' invalid", .0)]
-    Invalid(i128),
+    Invalid(String),
+
+    #[error("Undefined variable '{}'", .0)]
+    Undefined(String),
+
+    #[error("No previous result")]
+    NoAns,
+
+    #[error("No result ${}", .0)]
+    NoResult(usize),
+
+    #[error("Mismatched types '{}' and '{}'", .0, .1)]
+    TypeMismatch(IntType, IntType),
+
+    #[error("Division by zero in '{}'", .0)]
+    DivByZero(String),
+
+    #[error("Division overflow in '{}'", .0)]
+    DivOverflow(String),
+
+    #[error("'{}' overflowed in '{}'", .0, .1)]
+    Overflow(&'static str, String),
+
+    #[error("'{}' is a pair of values, which can't be used as an operand", .0)]
+    Pair(String),
+
+    #[error("'{}' isn't supported for type {}", .0, .1)]
+    Unsupported(String, IntType),
+
+    #[error("Undefined function '{}'", .0)]
+    UndefinedFunction(String),
+
+    #[error("'{}' takes {} argument(s), but {} were given", .0, .1, .2)]
+    Arity(String, usize, usize),
+
+    #[error("Calls nested more than {} deep, in call to '{}'", MAX_CALL_DEPTH, .0)]
+    CallDepth(String),
+
+    #[error("'{}' is too long for type {}", .0, .1)]
+    TooLong(String, IntType),
+
+    #[error("Bits [{}:{}] are out of range for type {}", .0, .1, .2)]
+    BitRange(i128, i128, IntType),
+
+    #[error("'{}' doesn't fit in a {}-bit field", .0, .1)]
+    FieldOverflow(String, u32),
+
+    /// An error in the subexpression at the given byte offsets in the input.
+    #[error("{}", .1)]
+    At(Range<usize>, Box<EvalErr>),
+}
+
+impl EvalErr {
+    /// Location of the error in the input, if it's known.
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            EvalErr::At(span, _) => Some(span.clone()),
+            _ => None,
+        }
+    }
+
+    /// The error, located at `span`, unless it's already located more
+    /// precisely (i.e., in a subexpression).
+    fn at(self, span: &Range<usize>) -> EvalErr {
+        match self {
+            EvalErr::At(..) => self,
+            e => EvalErr::At(span.clone(), Box::new(e)),
+        }
+    }
+}
+
+
+/// An integer type, signed or unsigned, of any width from 1 to 128 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+// Types given by C literal suffixes (see `parse_suffix()`), and by default.
+impl IntType {
+    pub const U32: Self = Self::new(false, 32);
+    pub const U64: Self = Self::new(false, 64);
+    pub const I64: Self = Self::new(true, 64);
+}
+
+impl IntType {
+    pub const fn new(signed: bool, bits: u32) -> Self {
+        IntType { signed, bits }
+    }
+
+    /// Width of the Rust type used to hold values of this type (see
+    /// `with_int_type!`).
+    pub fn container_bits(self) -> u32 {
+        self.bits.next_power_of_two().max(8)
+    }
+
+    /// Number of bits by which the container type `T` is wider than this type.
+    pub fn excess_bits<T: Int>(self) -> usize {
+        (T::zero().count_zeros() - self.bits) as usize
+    }
+
+    /// Truncates `val` to this type's width, sign- or zero-extending the result
+    /// to fill the container type `T`.
+    pub fn wrap<T: Int>(self, val: T) -> T {
+        let shift = self.excess_bits::<T>();
+        if shift == 0 {
+            val
+        } else {
+            // Shr is arithmetic for signed types and logical for unsigned.
+            (val << shift) >> shift
+        }
+    }
+
+    /// Smallest value of this type, in the container type `T`.
+    pub fn min<T: Int>(self) -> T {
+        T::min_value() >> self.excess_bits::<T>()
+    }
+
+    /// Largest value of this type, in the container type `T`.
+    pub fn max<T: Int>(self) -> T {
+        T::max_value() >> self.excess_bits::<T>()
+    }
+
+    /// Restricts `val`, in the container type `T`, to the range of this type.
+    pub fn clamp<T: Int>(self, val: T) -> T {
+        val.clamp(self.min(), self.max())
+    }
+
+    /// The bit pattern of `val`, zero-extended to 128 bits.
+    pub fn pattern<T: Int>(self, val: T) -> u128 {
+        IntType::new(false, self.bits).wrap(val.as_i128() as u128)
+    }
+
+    /// The value, in the container type `T`, with the bit pattern `pattern`.
+    pub fn pattern_value<T: Int>(self, pattern: u128) -> T {
+        self.wrap(T::from_i128_as(pattern as i128))
+    }
+}
+
+impl Default for IntType {
+    fn default() -> Self {
+        Self::U32
+    }
+}
+
+impl fmt::Display for IntType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", if self.signed { 'i' } else { 'u' }, self.bits)
+    }
+}
+
+impl FromStr for IntType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let signed = match s.get(..1) {
+            Some("u") => Some(false),
+            Some("i") => Some(true),
+            _ => None,
+        };
+        let bits = s.get(1..)
+            .and_then(|bits| bits.parse::<u32>().ok())
+            .filter(|bits| (1..=128).contains(bits));
+
+        match (signed, bits) {
+            (Some(signed), Some(bits)) => Ok(Self::new(signed, bits)),
+            _ => Err(format!("Invalid type '{s}' (expected u1 to u128 or i1 to i128)")),
+        }
+    }
+}
+
+/// Runs `$body` with `$t` as an alias for the Rust type that holds values of
+/// `$typ`.
+macro_rules! with_int_type {
+    ($typ:expr, $t:ident => $body:expr) => {{
+        let typ: $crate::expr::IntType = $typ;
+        match (typ.signed, typ.container_bits()) {
+            (false, 8) => { type $t = u8; $body }
+            (false, 16) => { type $t = u16; $body }
+            (false, 32) => { type $t = u32; $body }
+            (false, 64) => { type $t = u64; $body }
+            (false, 128) => { type $t = u128; $body }
+            (true, 8) => { type $t = i8; $body }
+            (true, 16) => { type $t = i16; $body }
+            (true, 32) => { type $t = i32; $body }
+            (true, 64) => { type $t = i64; $body }
+            (true, 128) => { type $t = i128; $body }
+            _ => unreachable!("Invalid type {typ}"),
+        }
+    }}
+}
+pub(crate) use with_int_type;
+
+
+/// A value, along with its type, stored as i128 (see `AsI128`).
+///
+/// Double-width results (see `Expr::WideMul` and `Expr::WideDiv`) are a pair
+/// of values, like a pair of registers; these can't be used as operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value {
+    pub typ: IntType,
+    bits: i128,
+    hi: Option<i128>,
+}
+
+impl Value {
+    pub fn new<T: Int>(typ: IntType, val: T) -> Self {
+        Value { typ, bits: val.as_i128(), hi: None }
+    }
+
+    pub fn pair<T: Int>(typ: IntType, hi: T, lo: T) -> Self {
+        Value { typ, bits: lo.as_i128(), hi: Some(hi.as_i128()) }
+    }
+
+    /// For a pair, the high half.
+    pub fn hi(&self) -> Option<Value> {
+        self.hi.map(|bits| Value { typ: self.typ, bits, hi: None })
+    }
+
+    /// For a pair, the low half; otherwise, the value itself.
+    pub fn lo(&self) -> Value {
+        Value { hi: None, ..*self }
+    }
+
+    pub fn get<T: Int>(&self) -> T {
+        T::from_i128_as(self.bits)
+    }
+
+    /// Converts to `typ`, following the semantics of Rust's `as`.
+    pub fn cast(self, typ: IntType) -> Self {
+        with_int_type!(typ, T => Value::new(typ, typ.wrap(self.get::<T>())))
+    }
+}
+
+
+/// Result of dividing by zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, strum::Display, ValueEnum)]
+#[strum(serialize_all = "kebab-case")]
+pub enum DivMode {
+    /// Report an error
+    #[default]
+    Error,
+    /// Quotient is all ones, remainder is the dividend
+    Riscv,
+    /// Quotient is zero, remainder is the dividend
+    Arm,
+    /// Report an error, as for signed overflow (MIN / -1)
+    X86,
+}
+
+
+/// Order in which the bytes of a byte string are packed into an integer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, strum::Display, ValueEnum)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Endian {
+    /// First byte is most significant, as written (e.g., as with C's
+    /// multi-character constants)
+    #[default]
+    Big,
+    /// First byte is least significant (e.g., as read from memory on a
+    /// little-endian machine)
+    Little,
+}
+
+/// Result of arithmetic that overflows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, strum::Display, ValueEnum)]
+#[strum(serialize_all = "kebab-case")]
+pub enum OverflowMode {
+    /// Wrap around (i.e., truncate)
+    #[default]
+    Wrap,
+    /// Clamp to the type's minimum or maximum value
+    Saturate,
+    /// Report an error
+    Checked,
+}
+
+
+/// Settings that affect evaluation.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Type of expressions that don't otherwise have one.
+    pub default_type: IntType,
+    pub div_mode: DivMode,
+    pub overflow: OverflowMode,
+    /// Whether non-decimal literals must fit in the type as a number, like
+    /// decimal ones, rather than giving a bit pattern (see `Lit`).
+    pub strict_literals: bool,
+    pub endian: Endian,
+}
+
+
+/// Maximum depth of nested calls to user-defined functions, so that runaway
+/// recursion is an error rather than a stack overflow.
+pub const MAX_CALL_DEPTH: usize = 256;
+
+/// A user-defined function.
+#[derive(Debug, Clone)]
+pub struct Func {
+    params: Vec<String>,
+    body: Expr,
+}
+
+/// An argument substituted for a parameter (see `Expr::expand()`), typed once,
+/// with its value cached for each type it's evaluated at.
+#[derive(Debug)]
+pub struct Arg {
+    expr: Expr,
+    typ: Option<IntType>,
+    vals: RefCell<Vec<(IntType, i128)>>,
+}
+
+/// Variables, functions, and previous results that persist between statements
+/// (i.e., across repl lines), along with the evaluation settings.
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    opts: Options,
+    vars: HashMap<String, Value>,
+    funcs: HashMap<String, Func>,
+    history: Vec<Value>,
+
+    // State of the statement being executed.
+    /// Depth of nested calls being evaluated.
+    call_depth: Cell<usize>,
+    /// Functions whose calls are being typed, with the types of their
+    /// arguments (see `Expr::typ()`).
+    typing: RefCell<Vec<(String, Vec<Option<IntType>>)>>,
+}
+
+impl Env {
+    pub fn new(opts: Options) -> Self {
+        Env { opts, ..Default::default() }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.vars.get(name).copied()
+    }
+
+    pub fn set(&mut self, name: &str, val: Value) {
+        self.vars.insert(name.to_owned(), val);
+    }
+
+    pub fn func(&self, name: &str) -> Option<&Func> {
+        self.funcs.get(name)
+    }
+
+    pub fn define(&mut self, name: &str, func: Func) {
+        self.funcs.insert(name.to_owned(), func);
+    }
+
+    /// Records a result, returning its number (starting from 1).
+    pub fn push_result(&mut self, val: Value) -> usize {
+        self.history.push(val);
+        self.history.len()
+    }
+
+    /// Result number `n`, as returned by `push_result()`.
+    pub fn result(&self, n: usize) -> Option<Value> {
+        let idx = n.checked_sub(1)?;
+        self.history.get(idx).copied()
+    }
+
+    pub fn last_result(&self) -> Option<Value> {
+        self.result(self.history.len())
+    }
+
+    /// All results, in order, paired with their numbers.
+    pub fn results(&self) -> impl Iterator<Item = (usize, Value)> + '_ {
+        self.history.iter().enumerate().map(|(i, v)| (i + 1, *v))
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Assign(String, Expr),
+    /// `def name(params...) = body`.
+    Def(String, Vec<String>, Expr),
+    Expr(Expr),
+}
+
+impl Stmt {
+    /// Evaluates the statement, returning the value of the expression (for
+    /// assignments, the value assigned), or None for definitions.
+    pub fn exec(&self, env: &mut Env) -> Result<Option<Value>, EvalErr> {
+        match self {
+            Stmt::Assign(name, e) => {
+                let val = e.eval(env)?;
+                env.set(name, val);
+                Ok(Some(val))
+            }
+            Stmt::Def(name, params, body) => {
+                env.define(name, Func { params: params.clone(), body: body.clone() });
+                Ok(None)
+            }
+            Stmt::Expr(e) => e.eval(env).map(Some),
+        }
+    }
+
+    /// As `exec()`, but also returns the flags set by the expression's top-level
+    /// operation, and returns None for definitions.
+    pub fn exec_flags(&self, env: &mut Env) -> Result<Option<(Value, Flags)>, EvalErr> {
+        match self {
+            Stmt::Assign(name, e) => {
+                let (val, flags) = e.eval_flags(env)?;
+                env.set(name, val);
+                Ok(Some((val, flags)))
+            }
+            Stmt::Def(name, params, body) => {
+                env.define(name, Func { params: params.clone(), body: body.clone() });
+                Ok(None)
+            }
+            Stmt::Expr(e) => e.eval_flags(env).map(Some),
+        }
+    }
+}
+
+
+/// Status flags, as set by a CPU for an operation. Operands and results are
+/// interpreted as both signed and unsigned, regardless of their type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    /// Negative: the top bit of the result is set.
+    pub n: bool,
+    /// Zero: the result is zero.
+    pub z: bool,
+    /// Carry: the operation overflowed as unsigned (for subtraction, it
+    /// borrowed, as on x86). For shifts, the last bit shifted out.
+    pub c: bool,
+    /// Overflow: the operation overflowed as signed.
+    pub v: bool,
 }
 
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Flags { n, z, c, v } = *self;
+        let (n, z, c, v) = (n as u8, z as u8, c as u8, v as u8);
+        write!(f, "N={n} Z={z} C={c} V={v}")
+    }
+}
+
+/// The evaluated operands of an operation (see `Expr::operands()`).
+#[derive(Debug, Clone, Copy)]
+enum Operands<T> {
+    Unary(T),
+    Binary(T, T),
+    /// A value, and the amount it's shifted by.
+    Shift(T, u32),
+}
+
+impl fmt::Display for Expr {
+    /// Writes the expression in the same syntax it's parsed from, with only the
+    /// necessary parentheses.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Writes a subexpression, parenthesized if it binds more loosely than
+        // `max_prec`.
+        let sub = |f: &mut fmt::Formatter<'_>, e: &Expr, max_prec: u32| {
+            if e.precedence() > max_prec {
+                write!(f, "({e})")
+            } else {
+                write!(f, "{e}")
+            }
+        };
+
+        use Expr::*;
+        let prec = self.precedence();
+        let op = self.operator().unwrap_or_default();
+        let (l, r) = match self {
+            Num(lit) => return write!(f, "{lit}"),
+            Str(bytes) => {
+                write!(f, "b\"")?;
+                for byte in bytes {
+                    match byte {
+                        b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+                        b' '..=b'~' => write!(f, "{}", *byte as char)?,
+                        _ => write!(f, "\\x{byte:02x}")?,
+                    }
+                }
+                return write!(f, "\"");
+            }
+            Var(name) => return write!(f, "{name}"),
+            Arg(arg) => return write!(f, "{}", arg.expr),
+            At(_, e) => return write!(f, "{e}"),
+            Ans => return write!(f, "ans"),
+            Hist(n) => return write!(f, "${n}"),
+
+            WideMul(a, b) => return write!(f, "{op}({a}, {b})"),
+            WideDiv(a, b, c) => return write!(f, "{op}({a}, {b}, {c})"),
+            Call(_, args) | UserCall(_, args) => {
+                let name = match self {
+                    UserCall(name, _) => name,
+                    _ => op,
+                };
+                let args: Vec<_> = args.iter().map(|a| a.to_string()).collect();
+                return write!(f, "{name}({})", args.join(", "));
+            }
+
+            Slice(e, hi, lo) | Insert(e, hi, lo, _) => {
+                sub(f, e, 1)?;
+                match lo {
+                    Some(lo) => write!(f, "[{hi}:{lo}]")?,
+                    None => write!(f, "[{hi}]")?,
+                }
+                return match self {
+                    Insert(.., val) => write!(f, " = {val}"),
+                    _ => Ok(()),
+                };
+            }
+
+            Neg(e) | Bitnot(e) | LogNot(e) => {
+                write!(f, "{op}")?;
+                return sub(f, e, prec);
+            }
+
+            // Right associative, so the condition needs parentheses at the
+            // same precedence.
+            Cond(c, a, b) => {
+                sub(f, c, prec - 1)?;
+                write!(f, " ? {a} : ")?;
+                return sub(f, b, prec);
+            }
+
+            Cast(e, typ) => {
+                sub(f, e, prec)?;
+                return write!(f, " {op} {typ}");
+            }
+
+            Mul(l, r) | Div(l, r) | Rem(l, r)
+                | Add(l, r) | Sub(l, r)
+                | Shr(l, r) | Shl(l, r) | Lsr(l, r) | Asr(l, r) | Rol(l, r) | Ror(l, r)
+                | Lt(l, r) | Le(l, r) | Gt(l, r) | Ge(l, r) | Eq(l, r) | Ne(l, r)
+                | And(l, r) | Xor(l, r) | Or(l, r)
+                | LogAnd(l, r) | LogOr(l, r) => (l, r),
+        };
+
+        // Binary operators are left associative, so the right side needs
+        // parentheses at the same precedence.
+        sub(f, l, prec)?;
+        write!(f, " {op} ")?;
+        sub(f, r, prec - 1)
+    }
+}
+
+
+/// Type of an expression made from `l` and `r`, which must agree unless one is
+/// untyped.
+fn unify(l: Option<IntType>, r: Option<IntType>) -> Result<Option<IntType>, EvalErr> {
+    match (l, r) {
+        (Some(l), Some(r)) if l != r => Err(EvalErr::TypeMismatch(l, r)),
+        (l, r) => Ok(l.or(r)),
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    // Precedence 1 (or parenthensized).
+    Num(Lit),
+    /// Byte string, packed into an integer.
+    Str(Vec<u8>),
+    Var(String),
+    Ans,
+    Hist(usize),
+    /// Full product, as a pair (high, low).
+    WideMul(Box<Expr>, Box<Expr>),
+    /// Division of a double-width dividend (high, low) by a divisor, giving
+    /// the pair (remainder, quotient) (i.e., as on x86).
+    WideDiv(Box<Expr>, Box<Expr>, Box<Expr>),
+    Call(Builtin, Vec<Expr>),
+    /// Call to a user-defined function.
+    UserCall(String, Vec<Expr>),
+    /// Argument substituted for a parameter, with the precedence of its
+    /// expression.
+    Arg(Rc<Arg>),
+    /// An expression, with its location in the input as byte offsets (for
+    /// errors), and the precedence of the expression.
+    At(Range<usize>, Box<Expr>),
+    /// `e[hi:lo]`, or `e[hi]` if there's no `lo`: the bits from `hi` down to
+    /// `lo`, inclusive, shifted down.
+    Slice(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+
+    // Precedence 2.
+    Neg(Box<Expr>),
+    Bitnot(Box<Expr>),
+    LogNot(Box<Expr>),
+
+    // Precedence 3.
+    Cast(Box<Expr>, IntType),
+
+    // Precedence 4.
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Rem(Box<Expr>, Box<Expr>),
+
+
+    // Precedence 5.
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+
+    // Precedence 6.
+    Shr(Box<Expr>, Box<Expr>),
+    Shl(Box<Expr>, Box<Expr>),
+    /// Logical right shift, regardless of signedness.
+    Lsr(Box<Expr>, Box<Expr>),
+    /// Arithmetic right shift, regardless of signedness.
+    Asr(Box<Expr>, Box<Expr>),
+    Rol(Box<Expr>, Box<Expr>),
+    Ror(Box<Expr>, Box<Expr>),
+
+    // Precedence 7.
+    Lt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Gt(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
+
+    // Precedence 8.
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+
+    // Precedence 9.
+    And(Box<Expr>, Box<Expr>),
+
+    // Precedence 10.
+    Xor(Box<Expr>, Box<Expr>),
+
+    // Precedence 11.
+    Or(Box<Expr>, Box<Expr>),
+
+    // Precedence 12.
+    LogAnd(Box<Expr>, Box<Expr>),
+
+    // Precedence 13.
+    LogOr(Box<Expr>, Box<Expr>),
+
+    // Precedence 14.
+    /// `cond ? then : else`.
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+
+    // Precedence 15 (only at the top level of a statement).
+    /// `e[hi:lo] = val`: `e` with the bits of `Slice(e, hi, lo)` replaced by
+    /// `val`.
+    Insert(Box<Expr>, Box<Expr>, Option<Box<Expr>>, Box<Expr>),
+}
+impl Expr {
+    /// Precedence, as numbered above (lower binds more tightly).
+    fn precedence(&self) -> u32 {
+        use Expr::*;
+        match self {
+            Arg(arg) => arg.expr.precedence(),
+            At(_, e) => e.precedence(),
+            Num(_) | Str(_) | Var(_) | Ans | Hist(_) => 1,
+            WideMul(..) | WideDiv(..) | Call(..) | UserCall(..) | Slice(..) => 1,
+            Neg(_) | Bitnot(_) | LogNot(_) => 2,
+            Cast(..) => 3,
+            Mul(..) | Div(..) | Rem(..) => 4,
+            Add(..) | Sub(..) => 5,
+            Shr(..) | Shl(..) | Lsr(..) | Asr(..) | Rol(..) | Ror(..) => 6,
+            Lt(..) | Le(..) | Gt(..) | Ge(..) => 7,
+            Eq(..) | Ne(..) => 8,
+            And(..) => 9,
+            Xor(..) => 10,
+            Or(..) => 11,
+            LogAnd(..) => 12,
+            LogOr(..) => 13,
+            Cond(..) => 14,
+            Insert(..) => 15,
+        }
+    }
+
+    /// The expression's operator (or function), as written.
+    fn operator(&self) -> Option<&'static str> {
+        use Expr::*;
+        Some(match self {
+            Num(_) | Str(_) | Var(_) | Ans | Hist(_) | UserCall(..) | Arg(_) | At(..) => return None,
+            Slice(..) | Insert(..) => return None,
+            WideMul(..) => "wmul",
+            WideDiv(..) => "wdiv",
+            Call(func, _) => func.name(),
+            Neg(_) => "-",
+            Bitnot(_) => "~",
+            LogNot(_) => "!",
+            Cast(..) => "as",
+            Mul(..) => "*",
+            Div(..) => "/",
+            Rem(..) => "%",
+            Add(..) => "+",
+            Sub(..) => "-",
+            Shr(..) => ">>",
+            Shl(..) => "<<",
+            Lsr(..) => "lsr",
+            Asr(..) => "asr",
+            Rol(..) => "rol",
+            Ror(..) => "ror",
+            Lt(..) => "<",
+            Le(..) => "<=",
+            Gt(..) => ">",
+            Ge(..) => ">=",
+            Eq(..) => "==",
+            Ne(..) => "!=",
+            And(..) => "&",
+            Xor(..) => "^",
+            Or(..) => "|",
+            LogAnd(..) => "&&",
+            LogOr(..) => "||",
+            Cond(..) => "?",
+        })
+    }
+
+    /// The type of the expression, or None if it's untyped (i.e., made up only
+    /// of literals), in which case it takes its type from context.
+    ///
+    /// As in Rust, both sides of a binary operator must have the same type,
+    /// except for shifts, where the amount can have any type. Comparisons and
+    /// logical operators give an untyped 0 or 1; the operands of logical
+    /// operators can have any type.
+    pub fn typ(&self, env: &Env) -> Result<Option<IntType>, EvalErr> {
+        use Expr::*;
+        Ok(match self {
+            Num(lit) => lit.typ,
+            Str(_) => None,
+            Var(_) | Ans | Hist(_) => Some(self.lookup(env)?.typ),
+            Arg(arg) => arg.typ,
+            At(span, e) => e.typ(env).map_err(|err| err.at(span))?,
+
+            WideMul(l, r) => unify(l.typ(env)?, r.typ(env)?)?,
+            Call(_, args) => args.iter().try_fold(None, |typ, a| unify(typ, a.typ(env)?))?,
+
+            // A recursive call (i.e., from the function's own body) doesn't
+            // contribute to its own type (which comes from the other branch of
+            // a conditional that ends the recursion), but its arguments must
+            // agree with those of the call being typed, since the body is
+            // evaluated at the same type. Arguments are typed before the
+            // function is marked as being typed, by `expand()`, so calls among
+            // them don't count.
+            UserCall(name, args) => {
+                let outer = env.typing.borrow().iter().rfind(|(f, _)| f == name).map(|(_, types)| types.clone());
+                match outer {
+                    Some(param_types) => {
+                        if args.len() != param_types.len() {
+                            return Err(EvalErr::Arity(name.clone(), param_types.len(), args.len()));
+                        }
+                        for (arg, param_type) in args.iter().zip(param_types) {
+                            unify(arg.typ(env)?, param_type)?;
+                        }
+                        None
+                    }
+                    None => {
+                        let (body, arg_types) = self.expand(env)?;
+                        env.typing.borrow_mut().push((name.clone(), arg_types));
+                        let typ = body.typ(env);
+                        env.typing.borrow_mut().pop();
+                        typ?
+                    }
+                }
+            }
+            WideDiv(hi, lo, d) => unify(unify(hi.typ(env)?, lo.typ(env)?)?, d.typ(env)?)?,
+
+            Neg(e) | Bitnot(e) => e.typ(env)?,
+
+            // The bit indexes, and the value inserted, can be of any type.
+            Slice(e, hi, lo) | Insert(e, hi, lo, _) => {
+                hi.typ(env)?;
+                if let Some(lo) = lo {
+                    lo.typ(env)?;
+                }
+                if let Insert(.., val) = self {
+                    val.typ(env)?;
+                }
+                e.typ(env)?
+            }
+
+            Cast(e, typ) => {
+                e.typ(env)?;
+                Some(*typ)
+            }
+
+            Shr(l, r) | Shl(l, r) | Lsr(l, r) | Asr(l, r) | Rol(l, r) | Ror(l, r) => {
+                r.typ(env)?;
+                l.typ(env)?
+            }
+
+            Mul(l, r) | Div(l, r) | Rem(l, r)
+                | Add(l, r) | Sub(l, r)
+                | And(l, r) | Xor(l, r) | Or(l, r) => unify(l.typ(env)?, r.typ(env)?)?,
+
+            Lt(l, r) | Le(l, r) | Gt(l, r) | Ge(l, r) | Eq(l, r) | Ne(l, r) => {
+                unify(l.typ(env)?, r.typ(env)?)?;
+                None
+            }
+
+            LogNot(e) => {
+                e.typ(env)?;
+                None
+            }
+            LogAnd(l, r) | LogOr(l, r) => {
+                l.typ(env)?;
+                r.typ(env)?;
+                None
+            }
+
+            Cond(c, a, b) => {
+                c.typ(env)?;
+                unify(a.typ(env)?, b.typ(env)?)?
+            }
+        })
+    }
+
+    /// The body of `self`, a `UserCall`, with its parameters replaced by the
+    /// arguments, which are typed first, along with the arguments' types.
+    ///
+    /// Arguments are substituted as expressions (see `Arg`), rather than
+    /// evaluated first, so that untyped arguments take their type from how
+    /// they're used.
+    fn expand(&self, env: &Env) -> Result<(Expr, Vec<Option<IntType>>), EvalErr> {
+        let Expr::UserCall(name, args) = self else {
+            unreachable!("Not a user call: {self}");
+        };
+        let func = env.func(name).ok_or_else(|| EvalErr::UndefinedFunction(name.clone()))?;
+        if args.len() != func.params.len() {
+            return Err(EvalErr::Arity(name.clone(), func.params.len(), args.len()));
+        }
+
+        let args = args.iter()
+            .map(|a| Ok(Rc::new(Arg { expr: a.clone(), typ: a.typ(env)?, vals: RefCell::default() })))
+            .collect::<Result<Vec<_>, EvalErr>>()?;
+
+        fn substitute(e: &mut Expr, params: &[String], args: &[Rc<Arg>]) {
+            if let Expr::Var(name) = e {
+                if let Some(i) = params.iter().position(|p| p == name) {
+                    *e = Expr::Arg(args[i].clone());
+                }
+                return;
+            }
+            for child in e.children_mut() {
+                substitute(child, params, args);
+            }
+        }
+        let mut body = func.body.clone();
+        substitute(&mut body, &func.params, &args);
+        Ok((body, args.iter().map(|a| a.typ).collect()))
+    }
+
+    /// Immediate subexpressions.
+    fn children_mut(&mut self) -> Vec<&mut Expr> {
+        use Expr::*;
+        match self {
+            Num(_) | Str(_) | Var(_) | Arg(_) | Ans | Hist(_) => vec![],
+            Call(_, args) | UserCall(_, args) => args.iter_mut().collect(),
+            At(_, e) | Neg(e) | Bitnot(e) | LogNot(e) | Cast(e, _) => vec![e],
+            WideDiv(a, b, c) | Cond(a, b, c) => vec![a, b, c],
+            Slice(e, hi, lo) => [Some(&mut **e), Some(&mut **hi), lo.as_deref_mut()]
+                .into_iter().flatten().collect(),
+            Insert(e, hi, lo, val) => [Some(&mut **e), Some(&mut **hi), lo.as_deref_mut(), Some(&mut **val)]
+                .into_iter().flatten().collect(),
+            WideMul(l, r)
+                | Mul(l, r) | Div(l, r) | Rem(l, r)
+                | Add(l, r) | Sub(l, r)
+                | Shr(l, r) | Shl(l, r) | Lsr(l, r) | Asr(l, r) | Rol(l, r) | Ror(l, r)
+                | Lt(l, r) | Le(l, r) | Gt(l, r) | Ge(l, r) | Eq(l, r) | Ne(l, r)
+                | And(l, r) | Xor(l, r) | Or(l, r)
+                | LogAnd(l, r) | LogOr(l, r) => vec![l, r],
+        }
+    }
+
+    /// The value of a `Var`, `Ans`, or `Hist`.
+    fn lookup(&self, env: &Env) -> Result<Value, EvalErr> {
+        use Expr::*;
+        match self {
+            Var(name) => env.get(name).ok_or_else(|| EvalErr::Undefined(name.clone())),
+            Ans => env.last_result().ok_or(EvalErr::NoAns),
+            Hist(n) => env.result(*n).ok_or(EvalErr::NoResult(*n)),
+            _ => unreachable!("Not a lookup: {self}"),
+        }
+    }
+
+    /// Evaluates the expression at its own type, or the environment's default
+    /// type if it's untyped.
+    pub fn eval_dyn(&self, env: &Env) -> Result<Value, EvalErr> {
+        let typ = self.typ(env)?.unwrap_or(env.opts.default_type);
+        with_int_type!(typ, T => Ok(Value::new(typ, self.eval_at::<T>(env, typ)?)))
+    }
+
+    /// Evaluates the expression as a whole, which, unlike subexpressions, may
+    /// be a pair.
+    pub fn eval(&self, env: &Env) -> Result<Value, EvalErr> {
+        use Expr::*;
+        match self {
+            At(span, e) => e.eval(env).map_err(|err| err.at(span)),
+            WideMul(..) | WideDiv(..) => self.eval_pair(env).map(|(val, _)| val),
+            Var(_) | Ans | Hist(_) => self.lookup(env),
+            _ => self.eval_dyn(env),
+        }
+    }
+
+    /// As `eval()`, also returning the flags set by the top-level operation.
+    pub fn eval_flags(&self, env: &Env) -> Result<(Value, Flags), EvalErr> {
+        use Expr::*;
+        match self {
+            At(span, e) => return e.eval_flags(env).map_err(|err| err.at(span)),
+            WideMul(..) | WideDiv(..) => return self.eval_pair(env),
+            Var(_) | Ans | Hist(_) => {
+                let val = self.lookup(env)?;
+                if val.hi().is_some() {
+                    return Ok((val, Flags::default()));
+                }
+            }
+            _ => (),
+        }
+
+        let typ = self.typ(env)?.unwrap_or(env.opts.default_type);
+        with_int_type!(typ, T => {
+            // The operands are evaluated only once, for both the result and
+            // the flags.
+            let operands = self.operands::<T>(env, typ)?;
+            let val = match operands {
+                Some(operands) => typ.wrap(self.apply::<T>(&env.opts, typ, operands)?),
+                None => self.eval_at::<T>(env, typ)?,
+            };
+            Ok((Value::new(typ, val), self.flags::<T>(typ, val, operands)))
+        })
+    }
+
+    /// Evaluates `self`, a `WideMul` or `WideDiv`, along with its flags.
+    ///
+    /// As on x86, a wide multiply sets carry and overflow if the high half is
+    /// significant; for a wide divide, they're always clear.
+    fn eval_pair(&self, env: &Env) -> Result<(Value, Flags), EvalErr> {
+        let typ = self.typ(env)?.unwrap_or(env.opts.default_type);
+        with_int_type!(typ, T => {
+            let operand = |e: &Expr| e.eval_at::<T>(env, typ).map(|v| typ.pattern(v));
+
+            let (hi, lo, significant) = match self {
+                Expr::WideMul(l, r) => {
+                    let (l, r) = (operand(l)?, operand(r)?);
+                    let (hi, lo) = wide::mul(l, r, typ.bits, typ.signed, typ.signed);
+                    // The high half is just an extension of the low half's sign.
+                    let extension = if typ.signed && (lo >> (typ.bits - 1)) & 1 != 0 {
+                        IntType::new(false, typ.bits).wrap(u128::MAX)
+                    } else {
+                        0
+                    };
+                    (hi, lo, hi != extension)
+                }
+                Expr::WideDiv(hi, lo, d) => {
+                    let (hi, lo, d) = (operand(hi)?, operand(lo)?, operand(d)?);
+                    if d == 0 {
+                        return Err(EvalErr::DivByZero(self.to_string()));
+                    }
+                    let (quot, rem) = wide::div(hi, lo, d, typ.bits, typ.signed)
+                        .ok_or_else(|| EvalErr::DivOverflow(self.to_string()))?;
+                    (rem, quot, false)
+                }
+                _ => unreachable!("Not a pair: {self}"),
+            };
+
+            let top_bit = |val: u128| (val >> (typ.bits - 1)) & 1 != 0;
+            let flags = match self {
+                Expr::WideMul(..) => Flags { n: top_bit(hi), z: hi == 0 && lo == 0, c: significant, v: significant },
+                _ => Flags { n: top_bit(lo), z: lo == 0, c: false, v: false },
+            };
+            let val = Value::pair::<T>(typ, typ.pattern_value(hi), typ.pattern_value(lo));
+            Ok((val, flags))
+        })
+    }
+
+    /// Evaluates the operands of `self`, if it's an operation whose flags
+    /// depend on them.
+    fn operands<T: Int>(&self, env: &Env, typ: IntType) -> Result<Option<Operands<T>>, EvalErr> {
+        use Expr::*;
+        Ok(Some(match self {
+            // A negative literal, rather than a negation (see `eval_at()`).
+            Neg(_) if T::is_signed() && self.negative_literal(&env.opts).is_some() => return Ok(None),
+            Neg(e) => Operands::Unary(e.eval_at::<T>(env, typ)?),
+            Mul(l, r) | Add(l, r) | Sub(l, r) => {
+                Operands::Binary(l.eval_at::<T>(env, typ)?, r.eval_at::<T>(env, typ)?)
+            }
+            Shr(l, r) | Shl(l, r) | Lsr(l, r) | Asr(l, r) | Rol(l, r) | Ror(l, r) => {
+                let l = l.eval_at::<T>(env, typ)?;
+                Operands::Shift(l, r.shift_amount::<T>(env, typ)?)
+            }
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Result of `self` given its `operands`.
+    fn apply<T: Int>(&self, opts: &Options, typ: IntType, operands: Operands<T>) -> Result<T, EvalErr> {
+        use Expr::*;
+        use Operands::*;
+        Ok(match (self, operands) {
+            (Neg(_), Unary(e)) => {
+                self.overflow(opts.overflow, typ, e.checked_neg(), e.wrapping_neg(), e.saturating_neg())?
+            }
+            (Mul(..), Binary(l, r)) => {
+                self.overflow(opts.overflow, typ, l.checked_mul(&r), l.wrapping_mul(&r), l.saturating_mul(&r))?
+            }
+            (Add(..), Binary(l, r)) => {
+                self.overflow(opts.overflow, typ, l.checked_add(&r), l.wrapping_add(&r), l.saturating_add(r))?
+            }
+            (Sub(..), Binary(l, r)) => {
+                self.overflow(opts.overflow, typ, l.checked_sub(&r), l.wrapping_sub(&r), l.saturating_sub(r))?
+            }
+
+            (Shr(..), Shift(l, amount)) => l.wrapping_shr(amount),
+            (Shl(..), Shift(l, amount)) => l.wrapping_shl(amount),
+            (Lsr(..), Shift(l, amount)) => typ.pattern_value(typ.pattern(l) >> amount),
+            (Asr(..), Shift(l, amount)) => {
+                let l = IntType::new(true, typ.bits).wrap(l.as_i128());
+                typ.pattern_value((l >> amount) as u128)
+            }
+            (Rol(..) | Ror(..), Shift(l, amount)) => {
+                let l = typ.pattern(l);
+                // A rotate right is a rotate left by the rest of the width.
+                let amount = if matches!(self, Ror(..)) { (typ.bits - amount) % typ.bits } else { amount };
+                if amount == 0 {
+                    typ.pattern_value(l)
+                } else {
+                    typ.pattern_value((l << amount) | (l >> (typ.bits - amount)))
+                }
+            }
+            _ => unreachable!("Operands don't match {self}"),
+        })
+    }
+
+    /// Flags set by the top-level operation, which gave `val` from `operands`.
+    fn flags<T: Int>(&self, typ: IntType, val: T, operands: Option<Operands<T>>) -> Flags {
+        // Operands, reinterpreted as both unsigned and signed. The results of
+        // the operations are exact, unless they overflow even 128 bits.
+        let unsigned = |val: T| IntType::new(false, typ.bits).wrap(val.as_i128() as u128);
+        let signed = |val: T| IntType::new(true, typ.bits).wrap(val.as_i128());
+        let u_fits = |res: Option<u128>| res.is_some_and(|res| res <= IntType::new(false, typ.bits).max());
+        let s_fits = |res: Option<i128>| res.is_some_and(|res| {
+            let s_typ = IntType::new(true, typ.bits);
+            (s_typ.min()..=s_typ.max()).contains(&res)
+        });
+        // Bit `i` of `val`.
+        let bit = |val: u128, i: u32| (val >> i) & 1 != 0;
+
+        use Expr::*;
+        use Operands::*;
+        let (c, v) = match (self, operands) {
+            (Add(..), Some(Binary(l, r))) => {
+                (!u_fits(unsigned(l).checked_add(unsigned(r))), !s_fits(signed(l).checked_add(signed(r))))
+            }
+            (Sub(..), Some(Binary(l, r))) => {
+                (unsigned(l) < unsigned(r), !s_fits(signed(l).checked_sub(signed(r))))
+            }
+            (Mul(..), Some(Binary(l, r))) => {
+                (!u_fits(unsigned(l).checked_mul(unsigned(r))), !s_fits(signed(l).checked_mul(signed(r))))
+            }
+            (Neg(_), Some(Unary(e))) => {
+                (unsigned(e) != 0, signed(e) == IntType::new(true, typ.bits).min())
+            }
+            (Shl(..), Some(Shift(l, amount))) => {
+                // Signed overflow if shifting back doesn't give the original.
+                let shifted = IntType::new(true, typ.bits).wrap(signed(l) << amount);
+                (amount > 0 && bit(unsigned(l), typ.bits - amount), shifted >> amount != signed(l))
+            }
+            (Shr(..) | Lsr(..) | Asr(..), Some(Shift(l, amount))) => {
+                (amount > 0 && bit(unsigned(l), amount - 1), false)
+            }
+            // As on x86, carry is the last bit rotated around.
+            (Rol(..), _) => (bit(unsigned(val), 0), false),
+            (Ror(..), _) => (bit(unsigned(val), typ.bits - 1), false),
+            // Including negative literals, which aren't operations.
+            _ => (false, false),
+        };
+
+        Flags {
+            n: bit(unsigned(val), typ.bits - 1),
+            z: val == T::zero(),
+            c,
+            v,
+        }
+    }
+
+    /// Evaluates `self`, an operand whose type needn't agree with its parent's,
+    /// at its own type if it has one, otherwise at the parent's type, `typ`.
+    fn eval_operand<T: Int>(&self, env: &Env, typ: IntType) -> Result<Value, EvalErr> {
+        match self.typ(env)? {
+            Some(_) => self.eval_dyn(env),
+            None => Ok(Value::new(typ, self.eval_at::<T>(env, typ)?)),
+        }
+    }
+
+    /// Shift amount given by `self`, mod the width of `typ`.
+    fn shift_amount<T: Int>(&self, env: &Env, typ: IntType) -> Result<u32, EvalErr> {
+        let amount = self.eval_operand::<T>(env, typ)?.bits;
+        Ok(amount.rem_euclid(typ.bits as i128) as u32)
+    }
+
+    /// The bits `(hi, lo)` selected by `self`, a `Slice` or `Insert` of a value
+    /// of type `typ`. The indexes must be in range for `typ`, and `hi` at least
+    /// `lo`.
+    fn bit_range(&self, env: &Env, typ: IntType) -> Result<(u32, u32), EvalErr> {
+        let (Expr::Slice(_, hi, lo) | Expr::Insert(_, hi, lo, _)) = self else {
+            unreachable!("Not a slice: {self}");
+        };
+        // Untyped indexes are evaluated as i128, rather than at `typ`, which
+        // may not be able to hold them (e.g., 8 for i4).
+        let index = |e: &Expr| -> Result<i128, EvalErr> {
+            match e.typ(env)? {
+                Some(_) => Ok(e.eval_dyn(env)?.bits),
+                None => e.eval_at::<i128>(env, IntType::new(true, 128)),
+            }
+        };
+        let hi = index(hi)?;
+        let lo = match lo {
+            Some(lo) => index(lo)?,
+            None => hi,
+        };
+        if !(0 <= lo && lo <= hi && hi < typ.bits as i128) {
+            return Err(EvalErr::BitRange(hi, lo, typ));
+        }
+        Ok((hi as u32, lo as u32))
+    }
+
+    /// Whether `self`, as the operand of a logical operator, is true (i.e.,
+    /// nonzero).
+    fn truth<T: Int>(&self, env: &Env, typ: IntType) -> Result<bool, EvalErr> {
+        Ok(self.eval_operand::<T>(env, typ)?.bits != 0)
+    }
+
+    /// Evaluates `self`, a comparison with operands `l` and `r`. The operands
+    /// are compared at their own type, or `typ` if they're untyped, so the
+    /// comparison is signed or unsigned accordingly.
+    fn compare<T: Int>(&self, env: &Env, typ: IntType, l: &Expr, r: &Expr) -> Result<bool, EvalErr> {
+        let operand_typ = unify(l.typ(env)?, r.typ(env)?)?.unwrap_or(typ);
+        with_int_type!(operand_typ, U => {
+            let (l, r) = (l.eval_at::<U>(env, operand_typ)?, r.eval_at::<U>(env, operand_typ)?);
+            // The container type has the same signedness, and values are sign-
+            // or zero-extended to fill it, so comparing them directly is enough.
+            Ok(match self {
+                Expr::Lt(..) => l < r,
+                Expr::Le(..) => l <= r,
+                Expr::Gt(..) => l > r,
+                Expr::Ge(..) => l >= r,
+                Expr::Eq(..) => l == r,
+                Expr::Ne(..) => l != r,
+                _ => unreachable!("Not a comparison: {self}"),
+            })
+        })
+    }
+
+    /// Result of `self`, an arithmetic operation, according to the overflow
+    /// `mode`, given its checked, wrapping, and saturating results in the
+    /// container type `T` (which may be wider than `typ`).
+    fn overflow<T: Int>(
+        &self,
+        mode: OverflowMode,
+        typ: IntType,
+        checked: Option<T>,
+        wrapping: T,
+        saturating: T,
+    ) -> Result<T, EvalErr> {
+        match (mode, checked.filter(|v| typ.wrap(*v) == *v)) {
+            (_, Some(val)) => Ok(val),
+            (OverflowMode::Wrap, None) => Ok(wrapping),
+            (OverflowMode::Saturate, None) => Ok(typ.clamp(saturating)),
+            (OverflowMode::Checked, None) => {
+                Err(EvalErr::Overflow(self.operator().unwrap(), self.to_string()))
+            }
+        }
+    }
 
-#[derive(Debug, Clone)]
-pub enum Expr {
-    // Precedence 1 (or parenthensized).
-    Num(i128),
+    /// Evaluates `self`, a `Div` or `Rem` with operands `l` and `r`.
+    fn div<T: Int>(&self, opts: &Options, typ: IntType, l: T, r: T) -> Result<T, EvalErr> {
+        let is_div = matches!(self, Expr::Div(..));
 
-    // Precedence 2.
-    Neg(Box<Expr>),
-    Bitnot(Box<Expr>),
+        if r == T::zero() {
+            return match opts.div_mode {
+                DivMode::Error | DivMode::X86 => Err(EvalErr::DivByZero(self.to_string())),
+                DivMode::Riscv if is_div => Ok(!T::zero()),
+                DivMode::Arm if is_div => Ok(T::zero()),
+                DivMode::Riscv | DivMode::Arm => Ok(l),
+            };
+        }
 
-    // Precedence 3 reserved for "as".
+        // The only overflow is MIN / -1, the quotient of which wraps back to MIN.
+        let quot = typ.wrap(l.wrapping_div(&r));
+        let overflow = typ.signed && r == !T::zero() && l != T::zero() && quot == l;
+        if opts.div_mode == DivMode::X86 && overflow {
+            return Err(EvalErr::DivOverflow(self.to_string()));
+        }
 
-    // Precedence 4.
-    Mul(Box<Expr>, Box<Expr>),
-    Div(Box<Expr>, Box<Expr>),
-    Rem(Box<Expr>, Box<Expr>),
+        // The remainder of MIN / -1 is zero, so it never overflows.
+        if is_div {
+            self.overflow(opts.overflow, typ, l.checked_div(&r), quot, l.saturating_div(&r))
+        } else {
+            Ok(l.wrapping_rem(&r))
+        }
+    }
 
+    /// `self`, or if it's an `Arg` or `At`, the expression it stands for.
+    pub fn unwrapped(&self) -> &Expr {
+        match self {
+            Expr::Arg(arg) => arg.expr.unwrapped(),
+            Expr::At(_, e) => e.unwrapped(),
+            e => e,
+        }
+    }
 
-    // Precedence 4.
-    Add(Box<Expr>, Box<Expr>),
-    Sub(Box<Expr>, Box<Expr>),
+    /// The expression at `span` in the input.
+    pub fn at(self, span: Range<usize>) -> Expr {
+        Expr::At(span, Box::new(self))
+    }
 
-    // Precedence 6.
-    Shr(Box<Expr>, Box<Expr>),
-    Shl(Box<Expr>, Box<Expr>),
+    /// `self`, without the locations of it or its subexpressions.
+    pub fn without_spans(mut self) -> Expr {
+        fn strip(e: &mut Expr) {
+            while let Expr::At(_, inner) = e {
+                let inner = std::mem::replace(&mut **inner, Expr::Ans);
+                *e = inner;
+            }
+            for child in e.children_mut() {
+                strip(child);
+            }
+        }
+        strip(&mut self);
+        self
+    }
 
-    // Precedence 7.
-    And(Box<Expr>, Box<Expr>),
+    /// If `self` is a negated literal that's a number, rather than a bit
+    /// pattern, the literal; such literals are negative numbers (e.g., `-128`
+    /// is valid for i8), rather than negations.
+    fn negative_literal(&self, opts: &Options) -> Option<&Lit> {
+        match self {
+            Expr::Neg(e) => match e.unwrapped() {
+                Expr::Num(lit) if !lit.is_pattern(opts) => Some(lit),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 
-    // Precedence 8.
-    Xor(Box<Expr>, Box<Expr>),
+    /// Evaluates the expression at type `typ`, held in `T` (see
+    /// `with_int_type!`). Typed subexpressions are assumed to agree with `typ`
+    /// (see `typ()`), with the exception of the operand of `as` and shift
+    /// amounts.
+    fn eval_at<T: Int>(&self, env: &Env, typ: IntType) -> Result<T, EvalErr> {
+        // Literals must fit in typ; everything else wraps.
+        let fits = |val: T| typ.wrap(val) == val;
 
-    // Precedence 9.
-    Or(Box<Expr>, Box<Expr>),
-}
-impl Expr {
-    pub fn eval<T: Int>(&self) -> Result<T, EvalErr> {
-        let t_bits = T::from(T::zero().count_zeros()).unwrap();
         use Expr::*;
-        Ok(match self {
-            Num(n) => T::from_i128(*n).ok_or(EvalErr::Invalid(*n))?,
+        Ok(typ.wrap(match self {
+            Num(lit) => lit.eval::<T>(&env.opts, typ)?,
+            Str(bytes) => {
+                if bytes.len() * 8 > typ.bits as usize {
+                    return Err(EvalErr::TooLong(self.to_string(), typ));
+                }
+                let pack = |val: u128, byte: &u8| (val << 8) | *byte as u128;
+                typ.pattern_value(match env.opts.endian {
+                    Endian::Big => bytes.iter().fold(0, pack),
+                    Endian::Little => bytes.iter().rev().fold(0, pack),
+                })
+            }
+            Var(_) | Ans | Hist(_) => {
+                let val = self.lookup(env)?;
+                if val.hi().is_some() {
+                    return Err(EvalErr::Pair(self.to_string()));
+                }
+                val.get()
+            }
+
+            WideMul(..) | WideDiv(..) => return Err(EvalErr::Pair(self.to_string())),
+            Call(func, args) => {
+                let args = args.iter().map(|a| a.eval_at::<T>(env, typ)).collect::<Result<Vec<_>, _>>()?;
+                func.eval(typ, &args).ok_or_else(|| EvalErr::Unsupported(self.to_string(), typ))?
+            }
+            Arg(arg) => {
+                if let Some((_, val)) = arg.vals.borrow().iter().find(|(t, _)| *t == typ) {
+                    return Ok(T::from_i128_as(*val));
+                }
+                let val = arg.expr.eval_at::<T>(env, typ)?;
+                arg.vals.borrow_mut().push((typ, val.as_i128()));
+                val
+            }
+            At(span, e) => return e.eval_at::<T>(env, typ).map_err(|err| err.at(span)),
+            UserCall(name, _) => {
+                let depth = env.call_depth.get();
+                if depth >= MAX_CALL_DEPTH {
+                    return Err(EvalErr::CallDepth(name.clone()));
+                }
+                let (body, _) = self.expand(env)?;
+                env.call_depth.set(depth + 1);
+                let val = body.eval_at::<T>(env, typ);
+                env.call_depth.set(depth);
+                val?
+            }
 
             Neg(e) => {
                 if T::is_signed() {
-                    if let Num(n) = &**e {
+                    if let Some(lit) = self.negative_literal(&env.opts) {
                         // For signe numbers, negation of a literal needs special 
                         // handling: -INT_MIN isn't representable, so do the negation 
-                        // before converting from i128.
-                        let val = n.wrapping_neg();
-                        return T::from_i128(val).ok_or(EvalErr::Invalid(val));
+                        // before converting from u128.
+                        return 0i128.checked_sub_unsigned(lit.val)
+                            .and_then(T::from_i128)
+                            .filter(|v| fits(*v))
+                            .ok_or_else(|| EvalErr::Invalid(format!("-{lit}")));
                     }
                 }
-                e.eval::<T>()?.wrapping_neg()
+                self.apply::<T>(&env.opts, typ, Operands::Unary(e.eval_at::<T>(env, typ)?))?
             }
 
-            Bitnot(e) => e.eval::<T>()?.not(),
+            Bitnot(e) => e.eval_at::<T>(env, typ)?.not(),
+            LogNot(e) => T::from_u8(!e.truth::<T>(env, typ)? as u8).unwrap(),
+
+            Cast(e, typ) => e.eval_dyn(env)?.cast(*typ).get(),
+
+            Mul(..) => self.apply::<T>(&env.opts, typ, self.operands::<T>(env, typ)?.unwrap())?,
+            Div(l, r) | Rem(l, r) => {
+                let (l, r) = (l.eval_at::<T>(env, typ)?, r.eval_at::<T>(env, typ)?);
+                self.div(&env.opts, typ, l, r)?
+            }
 
-            Mul(l, r) => l.eval::<T>()?.wrapping_mul(&r.eval::<T>()?),
-            Div(l, r) => l.eval::<T>()?.wrapping_div(&r.eval::<T>()?),
-            Rem(l, r) => l.eval::<T>()?.wrapping_rem(&r.eval::<T>()?),
+            Add(..) | Sub(..) => self.apply::<T>(&env.opts, typ, self.operands::<T>(env, typ)?.unwrap())?,
 
-            Add(l, r) => l.eval::<T>()?.wrapping_add(&r.eval::<T>()?),
-            Sub(l, r) => l.eval::<T>()?.wrapping_sub(&r.eval::<T>()?),
+            Shr(..) | Shl(..) | Lsr(..) | Asr(..) | Rol(..) | Ror(..) => {
+                self.apply::<T>(&env.opts, typ, self.operands::<T>(env, typ)?.unwrap())?
+            }
 
-            Shr(l, r) => l.eval::<T>()?.wrapping_shr((r.eval::<T>()? & (t_bits - T::one())).to_u32().unwrap()),
-            Shl(l, r) => l.eval::<T>()?.wrapping_shl((r.eval::<T>()? & (t_bits - T::one())).to_u32().unwrap()),
+            And(l, r) => l.eval_at::<T>(env, typ)?.bitand(r.eval_at::<T>(env, typ)?),
 
-            And(l, r) => l.eval::<T>()?.bitand(r.eval::<T>()?),
+            Xor(l, r) => l.eval_at::<T>(env, typ)?.bitxor(r.eval_at::<T>(env, typ)?),
 
-            Xor(l, r) => l.eval::<T>()?.bitxor(r.eval::<T>()?),
+            Or(l, r) => l.eval_at::<T>(env, typ)?.bitor(r.eval_at::<T>(env, typ)?),
 
-            Or(l, r) => l.eval::<T>()?.bitor(r.eval::<T>()?),
-        })
+            Lt(l, r) | Le(l, r) | Gt(l, r) | Ge(l, r) | Eq(l, r) | Ne(l, r) => {
+                T::from_u8(self.compare::<T>(env, typ, l, r)? as u8).unwrap()
+            }
+
+            // Short circuiting, as in C.
+            LogAnd(l, r) => {
+                T::from_u8((l.truth::<T>(env, typ)? && r.truth::<T>(env, typ)?) as u8).unwrap()
+            }
+            LogOr(l, r) => {
+                T::from_u8((l.truth::<T>(env, typ)? || r.truth::<T>(env, typ)?) as u8).unwrap()
+            }
+
+            // Only the chosen side is evaluated.
+            Slice(e, ..) => {
+                let (hi, lo) = self.bit_range(env, typ)?;
+                let field = typ.pattern(e.eval_at::<T>(env, typ)?) >> lo;
+                typ.pattern_value(IntType::new(false, hi - lo + 1).wrap(field))
+            }
+            Insert(e, .., val_expr) => {
+                let (hi, lo) = self.bit_range(env, typ)?;
+                let width = hi - lo + 1;
+                // The value must fit in the field, as unsigned, or for a
+                // signed type, as either signed or unsigned. An untyped value
+                // is taken as signed, one bit wider if `typ` is unsigned (up to
+                // 128 bits), so that e.g. -1 fits as well as the unsigned range.
+                let val = match val_expr.typ(env)? {
+                    Some(_) => val_expr.eval_dyn(env)?,
+                    None => {
+                        let bits = if typ.signed { typ.bits } else { (typ.bits + 1).min(128) };
+                        let s_typ = IntType::new(true, bits);
+                        with_int_type!(s_typ, S => Value::new(s_typ, val_expr.eval_at::<S>(env, s_typ)?))
+                    }
+                };
+                let u_fits = (val.bits >= 0 || !val.typ.signed)
+                    && IntType::new(false, width).wrap(val.bits as u128) == val.bits as u128;
+                let s_fits = val.typ.signed && IntType::new(true, width).wrap(val.bits) == val.bits;
+                let fits = u_fits || s_fits;
+                if !fits {
+                    return Err(EvalErr::FieldOverflow(val_expr.to_string(), width));
+                }
+
+                let mask = IntType::new(false, width).max::<u128>() << lo;
+                let pattern = typ.pattern(e.eval_at::<T>(env, typ)?);
+                typ.pattern_value((pattern & !mask) | ((val.bits as u128) << lo & mask))
+            }
+
+            Cond(c, a, b) => {
+                if c.truth::<T>(env, typ)? {
+                    a.eval_at::<T>(env, typ)?
+                } else {
+                    b.eval_at::<T>(env, typ)?
+                }
+            }
+        }))
     }
 
 }
@@ -98,44 +1617,84 @@ impl Expr {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::grammar::ExprParser;
+    use crate::grammar::{ExprParser, StmtParser};
 
-    use std::assert_matches::assert_matches;
+    use std::assert_matches;
     use std::thread_local;
 
     use lalrpop_util::ParseError;
 
+    /// `e`, without its location, so that tests can match on what it is.
+    fn unlocated(e: EvalErr) -> EvalErr {
+        match e {
+            EvalErr::At(_, e) => *e,
+            e => e,
+        }
+    }
+
+    /// Evaluates `e` at the full width of `T`.
+    fn eval_expr<T: Int>(e: &Expr, env: &Env) -> Result<T, EvalErr> {
+        e.eval_at::<T>(env, IntType::new(T::is_signed(), T::zero().count_zeros())).map_err(unlocated)
+    }
+
     fn eval<T: Int>(s: &str) -> T {
         thread_local! {
             static PARSER: ExprParser = Default::default();
         }
         PARSER.with(|p|
-            p.parse(s).unwrap().eval::<T>().unwrap()
+            eval_expr::<T>(&p.parse(s).unwrap(), &Env::default()).unwrap()
         )
     }
 
+    /// Executes `stmt`, returning its value. Panics for definitions, which
+    /// have no value.
+    fn exec_stmt(stmt: &Stmt, env: &mut Env) -> Result<Value, EvalErr> {
+        stmt.exec(env).map(|res| res.expect("Statement has no value")).map_err(unlocated)
+    }
+
+    /// Executes `src` in a new environment with the given options.
+    fn run(src: &str, opts: Options) -> Result<Value, EvalErr> {
+        thread_local! {
+            static PARSER: StmtParser = Default::default();
+        }
+        PARSER.with(|p| exec_stmt(&p.parse(src).unwrap(), &mut Env::new(opts)))
+    }
+
     #[test]
     fn malformed() {
         let parser = ExprParser::new();
+        let env = Env::default();
 
         assert_matches!(
-            parser.parse("1000000000000").unwrap().eval::<u32>(),
+            eval_expr::<u32>(&parser.parse("1000000000000").unwrap(), &env),
             Err(EvalErr::Invalid(_))
         );
         assert_matches!(
             parser.parse("0xg").unwrap_err(),
-            ParseError::InvalidToken{..},
+            ParseError::User { error: Error::LitParse { lit, span, .. } } if lit == "0xg" && span == (0..3),
         );
         assert_matches!(
-            parser.parse("0x1000000000000").unwrap().eval::<u32>(),
+            eval_expr::<u32>(&parser.parse("0x1000000000000").unwrap(), &env),
             Err(EvalErr::Invalid(_))
         );
         assert_matches!(
-            parser.parse("0o9").unwrap_err(),
-            ParseError::InvalidToken{..},
+            parser.parse("1 + 0o9").unwrap_err(),
+            ParseError::User { error: Error::LitParse { lit, span, .. } } if lit == "0o9" && span == (4..7),
+        );
+        assert_matches!(
+            parser.parse("0b12").unwrap_err(),
+            ParseError::User { error: Error::LitParse { msg, .. } } if msg == "invalid digit '2' for base 2",
+        );
+        assert_matches!(
+            parser.parse("123abc").unwrap_err(),
+            ParseError::User { error: Error::LitParse { msg, .. } } if msg == "invalid digit 'a' for base 10",
+        );
+        assert_matches!(
+            parser.parse("0x_").unwrap_err(),
+            ParseError::User { error: Error::LitParse { msg, .. } } if msg == "no digits",
         );
         assert_matches!(
-            parser.parse("0o1000000000000").unwrap().eval::<u32>(),
+            eval_expr::<u32>(&parser.parse("0o1000000000000").unwrap(), &env),
             Err(EvalErr::Invalid(_))
         );
         parser.parse("10 + 1)").unwrap_err();
@@ -146,7 +1705,7 @@ mod tests {
         parser.parse("10() + 1").unwrap_err();
 
         assert_matches!(
-            parser.parse("-256 - 1").unwrap().eval::<i8>(),
+            eval_expr::<i8>(&parser.parse("-256 - 1").unwrap(), &env),
             Err(EvalErr::Invalid(_))
         );
     }
@@ -159,10 +1718,231 @@ mod tests {
         assert_eq!(eval::<u32>("-5 + 6"), (-5i32 as u32).wrapping_add(6));
         assert_eq!(eval::<u32>("-5 + -6"), (-5i32 as u32).wrapping_add(-6i32 as u32));
 
-        assert_eq!(eval::<u32>("!0"), !0);
-        assert_eq!(eval::<u32>("!1"), !1);
-        assert_eq!(eval::<u32>("!32"), !32);
-        assert_eq!(eval::<u32>("!(-32)"), !(-32i32 as u32));
+        assert_eq!(eval::<u32>("~0"), !0);
+        assert_eq!(eval::<u32>("~1"), !1);
+        assert_eq!(eval::<u32>("~32"), !32);
+        assert_eq!(eval::<u32>("~(-32)"), !(-32i32 as u32));
+    }
+
+    #[test]
+    fn wide() {
+        assert_eq!(eval::<u128>("340282366920938463463374607431768211455"), u128::MAX);
+        assert_eq!(eval::<u128>("0xffffffffffffffffffffffffffffffff"), u128::MAX);
+        assert_eq!(eval::<u128>("-1"), u128::MAX);
+        assert_eq!(eval::<i128>("-170141183460469231731687303715884105728"), i128::MIN);
+        assert_eq!(eval::<i128>("0x7fffffffffffffffffffffffffffffff + 1"), i128::MIN);
+        assert_eq!(eval::<u128>("1 << 127 >> 64"), 1 << 63);
+        assert_eq!(eval::<u128>("1 << 128"), 1);
+        assert_eq!(eval::<u128>("-(1 as i128) as u128"), u128::MAX);
+
+        let parser = ExprParser::new();
+        let env = Env::default();
+        assert_matches!(
+            parser.parse("0x100000000000000000000000000000000").unwrap_err(),
+            ParseError::User{..},
+        );
+        assert_matches!(
+            eval_expr::<i128>(&parser.parse("170141183460469231731687303715884105728").unwrap(), &env),
+            Err(EvalErr::Invalid(_))
+        );
+        assert_matches!(
+            eval_expr::<i128>(&parser.parse("-170141183460469231731687303715884105729").unwrap(), &env),
+            Err(EvalErr::Invalid(n)) if n == "-170141183460469231731687303715884105729"
+        );
+    }
+
+    #[test]
+    fn narrow() {
+        let exec = |s: &str, typ: &str| run(s, Options { default_type: typ.parse().unwrap(), ..Default::default() });
+        let val = |v: i128, typ: &str| Value::new(typ.parse().unwrap(), v);
+
+        assert_eq!(exec("0xfff + 1", "u12").unwrap(), val(0, "u12"));
+        assert_eq!(exec("0 - 1", "u12").unwrap(), val(0xfff, "u12"));
+        assert_eq!(exec("2047 + 1", "i12").unwrap(), val(-2048, "i12"));
+        assert_eq!(exec("-2048 / -1", "i12").unwrap(), val(-2048, "i12"));
+        assert_eq!(exec("-2048 >> 4", "i12").unwrap(), val(-128, "i12"));
+        assert_eq!(exec("0x800 >> 4", "u12").unwrap(), val(0x80, "u12"));
+        assert_eq!(exec("~0", "u12").unwrap(), val(0xfff, "u12"));
+        assert_eq!(exec("0x123456 * 0x10", "u24").unwrap(), val(0x234560, "u24"));
+        assert_eq!(exec("1 << 13", "u12").unwrap(), val(2, "u12"));
+        assert_eq!(exec("1 << -1", "u12").unwrap(), val(1 << (0xfff % 12), "u12"));
+        assert_eq!(exec("1 + 1", "u1").unwrap(), val(0, "u1"));
+        assert_eq!(exec("-1", "i1").unwrap(), val(-1, "i1"));
+        assert_eq!(exec("0xabcdef as i12", "u32").unwrap(), val(-0x211, "i12"));
+        assert_eq!(exec("(0xabc as i12) as u48", "u32").unwrap(), val(0xffff_ffff_fabc, "u48"));
+        assert_eq!(exec("(0xabc as u12) as i48", "u32").unwrap(), val(0xabc, "i48"));
+        assert_eq!(exec("(1 as u100) << 99", "u32").unwrap(), val(1 << 99, "u100"));
+
+        assert_matches!(exec("4096", "u12"), Err(EvalErr::Invalid(_)));
+        assert_matches!(exec("2048", "i12"), Err(EvalErr::Invalid(_)));
+        assert_matches!(exec("-2049", "i12"), Err(EvalErr::Invalid(_)));
+        assert_eq!(exec("-2048", "i12").unwrap(), val(-2048, "i12"));
+
+        assert_eq!("u12".parse::<IntType>().unwrap(), IntType::new(false, 12));
+        assert_eq!("i128".parse::<IntType>().unwrap(), IntType::new(true, 128));
+        assert_eq!(IntType::new(true, 24).to_string(), "i24");
+        "u0".parse::<IntType>().unwrap_err();
+        "i129".parse::<IntType>().unwrap_err();
+        "x8".parse::<IntType>().unwrap_err();
+        "u".parse::<IntType>().unwrap_err();
+    }
+
+    #[test]
+    fn div_by_zero() {
+        let exec = |s: &str, typ: IntType, div_mode: DivMode| run(s, Options { default_type: typ, div_mode, ..Default::default() });
+
+        for mode in [DivMode::Error, DivMode::X86] {
+            assert_matches!(
+                exec("1 + 10 / (3 - 3)", IntType::U32, mode),
+                Err(EvalErr::DivByZero(e)) if e == "10 / (3 - 3)"
+            );
+            assert_matches!(
+                exec("(10 as u8) % 0 * 2", IntType::U32, mode),
+                Err(EvalErr::DivByZero(e)) if e == "10 as u8 % 0"
+            );
+        }
+        assert_eq!(exec("-128 / -1", IntType::new(true, 8), DivMode::Error).unwrap(), Value::new(IntType::new(true, 8), -128i8));
+        assert_matches!(
+            exec("-128 / -1", IntType::new(true, 8), DivMode::X86),
+            Err(EvalErr::DivOverflow(e)) if e == "-128 / -1"
+        );
+        assert_matches!(exec("-2048 % -1", IntType::new(true, 12), DivMode::X86), Err(EvalErr::DivOverflow(_)));
+        assert_eq!(exec("-127 / -1", IntType::new(true, 8), DivMode::X86).unwrap(), Value::new(IntType::new(true, 8), 127i8));
+        assert_eq!(exec("255 / 255", IntType::new(false, 8), DivMode::X86).unwrap(), Value::new(IntType::new(false, 8), 1u8));
+
+        assert_eq!(exec("7 / 0", IntType::new(false, 8), DivMode::Riscv).unwrap(), Value::new(IntType::new(false, 8), 0xffu8));
+        assert_eq!(exec("7 / 0", IntType::new(true, 8), DivMode::Riscv).unwrap(), Value::new(IntType::new(true, 8), -1i8));
+        assert_eq!(exec("7 / 0", IntType::new(false, 12), DivMode::Riscv).unwrap(), Value::new(IntType::new(false, 12), 0xfffu16));
+        assert_eq!(exec("7 % 0", IntType::new(false, 8), DivMode::Riscv).unwrap(), Value::new(IntType::new(false, 8), 7u8));
+        assert_eq!(exec("7 / 0", IntType::new(false, 8), DivMode::Arm).unwrap(), Value::new(IntType::new(false, 8), 0u8));
+        assert_eq!(exec("-7 % 0", IntType::new(true, 8), DivMode::Arm).unwrap(), Value::new(IntType::new(true, 8), -7i8));
+    }
+
+    #[test]
+    fn overflow() {
+        let exec = |s: &str, typ: &str, overflow: OverflowMode| {
+            run(s, Options { default_type: typ.parse().unwrap(), overflow, ..Default::default() }).map(|v| v.get::<i128>())
+        };
+
+        use OverflowMode::*;
+        for (s, typ, wrap, saturate) in [
+            ("200 + 100", "u8", 44, 255),
+            ("100 + 100", "i8", -56, 127),
+            ("-100 + -100", "i8", 56, -128),
+            ("3 - 4", "u8", 255, 0),
+            ("-100 - 100", "i8", 56, -128),
+            ("100 - -100", "i8", -56, 127),
+            ("16 * 16", "u8", 0, 255),
+            ("-16 * 16", "i8", 0, -128),
+            ("-16 * -16", "i8", 0, 127),
+            ("-1", "u8", 255, 0),
+            ("-(-128)", "i8", -128, 127),
+            ("-128 / -1", "i8", -128, 127),
+            ("0xfff + 1", "u12", 0, 0xfff),
+            ("2047 + 1", "i12", -2048, 2047),
+            ("-2048 - 1", "i12", 2047, -2048),
+            ("-2048 / -1", "i12", -2048, 2047),
+            ("-(-2048)", "i12", -2048, 2047),
+            ("0x1000000 * 0x1000000", "u48", 0, (1 << 48) - 1),
+            ("-0x1000000 * 0x1000000", "i48", 0, -(1 << 47)),
+            ("0x100000000 * 0x100000000", "u64", 0, u64::MAX as i128),
+        ] {
+            assert_eq!(exec(s, typ, Wrap).unwrap(), wrap, "{s}: {typ}");
+            assert_eq!(exec(s, typ, Saturate).unwrap(), saturate, "{s}: {typ}");
+            assert_matches!(exec(s, typ, Checked), Err(EvalErr::Overflow(..)), "{s}: {typ}");
+        }
+
+        for mode in [Wrap, Saturate, Checked] {
+            assert_eq!(exec("254 + 1", "u8", mode).unwrap(), 255);
+            assert_eq!(exec("-128", "i8", mode).unwrap(), -128);
+            assert_eq!(exec("-128 % -1", "i8", mode).unwrap(), 0);
+            assert_eq!(exec("1 << 7", "i8", mode).unwrap(), -128);
+            assert_eq!(exec("300 as u8", "u32", mode).unwrap(), 44);
+        }
+
+        assert_matches!(
+            exec("1 + 2 * (100 + 100)", "i8", Checked),
+            Err(EvalErr::Overflow("+", e)) if e == "100 + 100"
+        );
+        assert_matches!(
+            exec("1 + 2 * 100", "i8", Checked),
+            Err(EvalErr::Overflow("*", e)) if e == "2 * 100"
+        );
+    }
+
+    #[test]
+    fn flags() {
+        let parser = StmtParser::new();
+        let flags = |s: &str, typ: &str| {
+            let mut env = Env::new(Options { default_type: typ.parse().unwrap(), ..Default::default() });
+            let (_, flags) = parser.parse(s).unwrap().exec_flags(&mut env).unwrap().unwrap();
+            flags.to_string()
+        };
+
+        assert_eq!(flags("1 + 1", "u8"), "N=0 Z=0 C=0 V=0");
+        assert_eq!(flags("255 + 1", "u8"), "N=0 Z=1 C=1 V=0");
+        assert_eq!(flags("127 + 1", "u8"), "N=1 Z=0 C=0 V=1");
+        assert_eq!(flags("127 + 1", "i8"), "N=1 Z=0 C=0 V=1");
+        assert_eq!(flags("-1 + -1", "i8"), "N=1 Z=0 C=1 V=0");
+        assert_eq!(flags("-128 + -1", "i8"), "N=0 Z=0 C=1 V=1");
+        assert_eq!(flags("1 - 2", "u8"), "N=1 Z=0 C=1 V=0");
+        assert_eq!(flags("2 - 2", "u8"), "N=0 Z=1 C=0 V=0");
+        assert_eq!(flags("-128 - 1", "i8"), "N=0 Z=0 C=0 V=1");
+        assert_eq!(flags("16 * 16", "u8"), "N=0 Z=1 C=1 V=1");
+        assert_eq!(flags("8 * 16", "i8"), "N=1 Z=0 C=0 V=1");
+        assert_eq!(flags("-1 * -1", "i8"), "N=0 Z=0 C=1 V=0");
+        assert_eq!(flags("-0", "u8"), "N=0 Z=1 C=0 V=0");
+        assert_eq!(flags("-1", "u8"), "N=1 Z=0 C=1 V=0");
+        assert_eq!(flags("-(-128)", "i8"), "N=1 Z=0 C=1 V=1");
+        assert_eq!(flags("-128", "i8"), "N=1 Z=0 C=0 V=0");
+        assert_eq!(flags("0xc0 << 1", "u8"), "N=1 Z=0 C=1 V=0");
+        assert_eq!(flags("0x40 << 1", "u8"), "N=1 Z=0 C=0 V=1");
+        assert_eq!(flags("0x40 << 0", "u8"), "N=0 Z=0 C=0 V=0");
+        assert_eq!(flags("0x03 >> 1", "u8"), "N=0 Z=0 C=1 V=0");
+        assert_eq!(flags("0xfff + 1", "u12"), "N=0 Z=1 C=1 V=0");
+        assert_eq!(flags("0x7ff + 1", "u12"), "N=1 Z=0 C=0 V=1");
+        assert_eq!(flags("-1 + -1", "u128"), "N=1 Z=0 C=1 V=0");
+        assert_eq!(flags("(1 << 127) + (1 << 127)", "i128"), "N=0 Z=1 C=1 V=1");
+        assert_eq!(flags("x = 0x80 & 0xff", "u8"), "N=1 Z=0 C=0 V=0");
+        assert_eq!(flags("0x81 rol 1", "u8"), "N=0 Z=0 C=1 V=0");
+        assert_eq!(flags("0x81 ror 1", "u8"), "N=1 Z=0 C=1 V=0");
+        assert_eq!(flags("0x82 ror 1", "u8"), "N=0 Z=0 C=0 V=0");
+        assert_eq!(flags("0x83 asr 1", "u8"), "N=1 Z=0 C=1 V=0");
+    }
+
+    #[test]
+    fn display() {
+        let parser = ExprParser::new();
+        for s in [
+            "1 + 2 * 3",
+            "(1 + 2) * 3",
+            "1 - (2 - 3)",
+            "1 - 2 - 3",
+            "-(x as u8) as i32",
+            "-x as u8",
+            "~~(1 << 2 & 3 ^ 4 | 5)",
+            "(1 | 2) ^ 3 & 4 >> 5",
+            "ans % $2 / -_t",
+            "1 rol 2 ror 3 lsr (4 asr 5)",
+            "!(1 < 2) == 3 >= 4 != 5 <= (6 > 7)",
+            "1 || 2 && (3 || 4) && !5 | 6 & 7",
+            "1 ? 2 : 3 ? 4 : 5",
+            "popcnt(x) + mulhu(1, 2 + 3) * wmul(4, 5)",
+            "f() + g(x, -(1 + 2), h(3))",
+            r#"b"ELF\x7f\"\\" | b"""#,
+            "x[7:4] + -y[0] * (a + b)[i + 1:0][3]",
+                ] {
+            assert_eq!(parser.parse(s).unwrap().to_string(), s);
+        }
+        assert_eq!(parser.parse("((1)) + (2 * 3)").unwrap().to_string(), "1 + 2 * 3");
+        assert_eq!(parser.parse("~0x10 + _").unwrap().to_string(), "~16 + ans");
+        assert_matches!(
+            StmtParser::new().parse("x[7:4] = 1 + 2").unwrap(),
+            Stmt::Assign(name, e) if name == "x" && e.to_string() == "x[7:4] = 1 + 2",
+        );
+        assert_eq!(parser.parse(r#"'A' + b"\n\u{e9}""#).unwrap().to_string(), r#"65 + b"\x0a\xc3\xa9""#);
+        assert_eq!(parser.parse("(a ? b : c) ? (d ? e : f) : (g ? h : i)").unwrap().to_string(),
+            "(a ? b : c) ? d ? e : f : g ? h : i");
     }
 
     #[test]
@@ -170,6 +1950,587 @@ mod tests {
         assert_eq!(eval::<u32>("0xf"), 15);
         assert_eq!(eval::<u32>("0o20"), 16);
         assert_eq!(eval::<u32>("0xf ^ 0o20"), 31);
+        assert_eq!(eval::<u32>("0b1010"), 10);
+        assert_eq!(eval::<u32>("0b1 + 0b0"), 1);
+        assert_eq!(eval::<u32>("0xFFFF_0000"), 0xffff_0000);
+        assert_eq!(eval::<u32>("1_000_000"), 1_000_000);
+        assert_eq!(eval::<u32>("0b_1010__0101_"), 0b1010_0101);
+        assert_eq!(eval::<u32>("0o7_7"), 0o77);
+        assert_eq!(eval::<u32>("0_1"), 1);
+        assert_eq!(eval::<u32>("36#zz"), 1295);
+        assert_eq!(eval::<u32>("36#ZZ"), 1295);
+        assert_eq!(eval::<u32>("0r36_zz"), 1295);
+        assert_eq!(eval::<u32>("2#1010 + 4#33"), 25);
+        assert_eq!(eval::<u32>("0r32_10_00"), 32 * 32 * 32);
+        assert_eq!(eval::<u32>("10#99"), 99);
+        assert_eq!(eval::<i8>("16#ff"), -1);
+        assert_eq!(eval::<i8>("10#99"), 99);
+
+        let parser = ExprParser::new();
+        assert_eq!(parser.parse("20#1ju8").unwrap().typ(&Env::default()).unwrap(), Some(IntType::new(false, 8)));
+        assert_eq!(parser.parse("36#1ju8").unwrap().typ(&Env::default()).unwrap(), None);
+        for (src, msg) in [
+            ("37#1", "invalid base '37' (expected 2 to 36)"),
+            ("1#1", "invalid base '1' (expected 2 to 36)"),
+            ("0r_1", "invalid base '' (expected 2 to 36)"),
+            ("0r36", "expected '_' after the base"),
+            ("3#123", "invalid digit '3' for base 3"),
+            ("16#", "no digits"),
+        ] {
+            match parser.parse(src) {
+                Err(ParseError::User { error: Error::LitParse { msg: m, .. } }) => assert_eq!(m, msg, "{src}"),
+                res => panic!("Unexpected result for {src}: {res:?}"),
+            }
+        }
+    }
+
+
+    #[test]
+    fn pattern_literal() {
+        assert_eq!(eval::<i8>("0xff"), -1);
+        assert_eq!(eval::<i8>("0x80"), -128);
+        assert_eq!(eval::<i8>("0x7f"), 127);
+        assert_eq!(eval::<i8>("0b1000_0001"), -127);
+        assert_eq!(eval::<i8>("0o377"), -1);
+        assert_eq!(eval::<i8>("-0x01"), -1);
+        assert_eq!(eval::<i8>("-0xff"), 1);
+        assert_eq!(eval::<i128>("0xffffffffffffffffffffffffffffffff"), -1);
+        assert_eq!(eval::<i32>("0xffff_ff80 as i8"), -128);
+
+        let exec = |s: &str, typ: &str, strict_literals: bool| {
+            run(s, Options { default_type: typ.parse().unwrap(), strict_literals, ..Default::default() })
+        };
+        let val = |v: i128, typ: &str| {
+            let typ: IntType = typ.parse().unwrap();
+            with_int_type!(typ, T => Value::new(typ, T::from_i128(v).unwrap()))
+        };
+
+        assert_eq!(exec("0xfff", "i12", false).unwrap(), val(-1, "i12"));
+        assert_eq!(exec("0x800 + 1", "i12", false).unwrap(), val(-2047, "i12"));
+        assert_matches!(exec("0x1000", "i12", false), Err(EvalErr::Invalid(_)));
+        assert_matches!(exec("2048", "i12", false), Err(EvalErr::Invalid(_)));
+        assert_eq!(exec("-2048", "i12", false).unwrap(), val(-2048, "i12"));
+
+        // -0x80 is the negation of -128, which overflows.
+        assert_eq!(exec("-0x80", "i8", false).unwrap(), val(-128, "i8"));
+        let checked = Options { default_type: IntType::new(true, 8), overflow: OverflowMode::Checked, ..Default::default() };
+        assert_matches!(run("-0x80", checked), Err(EvalErr::Overflow("-", _)));
+
+        // Strict literals must fit as numbers, as decimal literals do.
+        assert_matches!(exec("0xff", "i8", true), Err(EvalErr::Invalid(_)));
+        assert_matches!(exec("0b1000_0000", "i8", true), Err(EvalErr::Invalid(_)));
+        assert_eq!(exec("0x7f", "i8", true).unwrap(), val(127, "i8"));
+        assert_eq!(exec("-0x80", "i8", true).unwrap(), val(-128, "i8"));
+        assert_eq!(exec("0xff", "u8", true).unwrap(), val(255, "u8"));
+    }
+
+    #[test]
+    fn bit_slice() {
+        assert_eq!(eval::<u32>("0xabcd[11:4]"), 0xbc);
+        assert_eq!(eval::<u32>("0xabcd[15]"), 1);
+        assert_eq!(eval::<u32>("0xabcd[1]"), 0);
+        assert_eq!(eval::<u32>("0xabcd[31:0]"), 0xabcd);
+        assert_eq!(eval::<u32>("0xabcd[7:0][7:4]"), 0xc);
+        assert_eq!(eval::<u32>("-0x80[7]"), u32::MAX);
+        assert_eq!(eval::<u32>("0xf0[1 + 6:2 * 2] << 1"), 0x1e);
+        assert_eq!(eval::<i8>("0x80[7:0]"), -128);
+        assert_eq!(eval::<i8>("(-1)[7:4]"), 15);
+        assert_eq!(eval::<u128>("(1 << 127)[127]"), 1);
+
+        let parser = StmtParser::new();
+        let mut env = Env::default();
+        let mut exec = |s: &str| exec_stmt(&parser.parse(s).unwrap(), &mut env);
+        assert_eq!(exec("0x12[7:4] = 0xa").unwrap().get::<u32>(), 0xa2);
+        assert_eq!(exec("0x12[0] = 1").unwrap().get::<u32>(), 0x13);
+        assert_eq!(exec("0i8[3:0] = -1").unwrap().get::<i8>(), 0x0f);
+        assert_eq!(exec("0i8[7:4] = 0xf").unwrap().get::<i8>(), -16);
+        assert_eq!(exec("5[3:0] = -1").unwrap().get::<u32>(), 0xf);
+        assert_eq!(exec("5u8[3:0] = -8").unwrap().get::<u8>(), 0x8);
+        assert_eq!(exec("5u8[7:0] = 255").unwrap().get::<u8>(), 0xff);
+        assert_eq!(exec("(0 as u128)[127:0] = -1").unwrap().get::<u128>(), u128::MAX);
+        assert_matches!(exec("5u8[3:0] = -9"), Err(EvalErr::FieldOverflow(_, 4)));
+        assert_matches!(exec("5u8[3:0] = 16"), Err(EvalErr::FieldOverflow(_, 4)));
+        exec("x = 0x1234u16").unwrap();
+        assert_eq!(exec("x[7:4] = 0xa").unwrap().get::<u16>(), 0x12a4);
+        assert_eq!(exec("x").unwrap().get::<u16>(), 0x12a4);
+        assert_eq!(exec("x[15:12] = -1i4").unwrap().get::<u16>(), 0xf2a4);
+        assert_eq!(exec("x[15:12]").unwrap().typ, IntType::new(false, 16));
+        assert_eq!(exec("x[3u8:0i64]").unwrap().get::<u16>(), 4);
+        assert_matches!(exec("x[16]"), Err(EvalErr::BitRange(16, 16, IntType { signed: false, bits: 16 })));
+        assert_matches!(exec("x[3:4]"), Err(EvalErr::BitRange(3, 4, _)));
+        assert_matches!(exec("x[3:-1i8]"), Err(EvalErr::BitRange(3, -1, _)));
+        assert_matches!(exec("(0 as i4)[8]"), Err(EvalErr::BitRange(8, 8, IntType { signed: true, bits: 4 })));
+        assert_eq!(exec("(-1 as i4)[3:0]").unwrap().get::<i8>(), -1);
+        assert_matches!(exec("x[7:4] = 0x10"), Err(EvalErr::FieldOverflow(_, 4)));
+        assert_matches!(exec("x[0] = -1u8"), Err(EvalErr::FieldOverflow(_, 1)));
+        assert_eq!(exec("x").unwrap().get::<u16>(), 0xf2a4);
+
+        assert_matches!(
+            parser.parse("1 + x = 2").unwrap_err(),
+            ParseError::User { error: Error::AssignTarget(target) } if target == "1 + x",
+        );
+    }
+
+    #[test]
+    fn typed_literal() {
+        let exec = |s: &str| run(s, Options::default());
+        let val = |v: i128, typ: &str| {
+            let typ: IntType = typ.parse().unwrap();
+            with_int_type!(typ, T => Value::new(typ, T::from_i128(v).unwrap()))
+        };
+
+        assert_eq!(exec("0xffu8").unwrap(), val(255, "u8"));
+        assert_eq!(exec("0xffi8").unwrap(), val(-1, "i8"));
+        assert_eq!(exec("-1i16").unwrap(), val(-1, "i16"));
+        assert_eq!(exec("-128i8").unwrap(), val(-128, "i8"));
+        assert_eq!(exec("0b1_u3 + 7").unwrap(), val(0, "u3"));
+        assert_eq!(exec("2 * 100u8").unwrap(), val(200, "u8"));
+        assert_eq!(exec("1u8 << 2u64").unwrap(), val(4, "u8"));
+        assert_eq!(exec("1u8 < 2").unwrap(), val(1, "u32"));
+        assert_eq!(exec("5U").unwrap(), val(5, "u32"));
+        assert_eq!(exec("-1L").unwrap(), val(-1, "i64"));
+        assert_eq!(exec("1ull << 63").unwrap(), val(1 << 63, "u64"));
+        assert_eq!(exec("7LU").unwrap(), val(7, "u64"));
+        assert_matches!(exec("128i8"), Err(EvalErr::Invalid(_)));
+        assert_matches!(exec("0x100u8"), Err(EvalErr::Invalid(_)));
+        assert_matches!(exec("1u8 + 1u16"), Err(EvalErr::TypeMismatch(IntType { signed: false, bits: 8 }, IntType { signed: false, bits: 16 })));
+        assert_matches!(exec("1U + 1UL"), Err(EvalErr::TypeMismatch(IntType::U32, IntType::U64)));
+
+        let parser = ExprParser::new();
+        for (src, msg) in [
+            ("1u0", "invalid suffix 'u0'"),
+            ("1i129", "invalid suffix 'i129'"),
+            ("1UUL", "invalid suffix 'UUL'"),
+            ("0xu8", "no digits"),
+        ] {
+            assert_matches!(
+                parser.parse(src).unwrap_err(),
+                ParseError::User { error: Error::LitParse { msg: m, .. } } if m == msg,
+            );
+        }
+        assert_eq!(parser.parse("0xffu8 + -1_i16").unwrap().to_string(), "255u8 + -1i16");
+    }
+
+    #[test]
+    fn string_literal() {
+        assert_eq!(eval::<u32>("'A'"), 65);
+        assert_eq!(eval::<u32>(r"'\n' + '\t'"), 19);
+        assert_eq!(eval::<u32>(r"'\''"), 39);
+        assert_eq!(eval::<i8>(r"'\xff'"), -1);
+        assert_eq!(eval::<u32>(r"'\u{1F600}'"), 0x1f600);
+        assert_eq!(eval::<u32>("'é'"), 0xe9);
+        assert_eq!(eval::<u32>(r#"b"ELF\x7f""#), 0x454c467f);
+        assert_eq!(eval::<u32>(r#"b"\"\\""#), 0x225c);
+        assert_eq!(eval::<u32>(r#"b"é""#), 0xc3a9);
+        assert_eq!(eval::<i16>(r#"b"\xff\xfe""#), -2);
+        assert_eq!(eval::<u32>(r#"b"""#), 0);
+
+        let exec = |s: &str, typ: &str, endian: Endian| {
+            run(s, Options { default_type: typ.parse().unwrap(), endian, ..Default::default() }).map(|v| v.get::<u64>())
+        };
+        assert_eq!(exec(r#"b"ELF\x7f""#, "u32", Endian::Little).unwrap(), 0x7f464c45);
+        assert_eq!(exec(r#"b"AB" as u64"#, "u16", Endian::Little).unwrap(), 0x4241);
+        assert_eq!(exec(r#"b"ABCD" as u64"#, "u24", Endian::Big).unwrap_err().to_string(),
+            r#"'b"ABCD"' is too long for type u24"#);
+        assert_matches!(exec(r#"b"ABC""#, "u16", Endian::Big), Err(EvalErr::TooLong(..)));
+        assert_eq!(exec("'é' as i64", "i8", Endian::Big).unwrap(), -23i64 as u64);
+        assert_matches!(exec(r"'\u{100}'", "i8", Endian::Big), Err(EvalErr::Invalid(_)));
+
+        let expr_parser = ExprParser::new();
+        for (src, msg) in [
+            ("''", None),
+            ("'ab'", Some("must be exactly one character")),
+            (r"'\q'", Some(r"unknown escape '\q'")),
+            (r#"b"\xg0""#, Some(r"invalid escape '\xg0'")),
+            (r"'\u{110000}'", Some(r"invalid escape '\u{110000}'")),
+            (r"'\u41'", Some(r"invalid escape '\u'")),
+        ] {
+            match (expr_parser.parse(src).unwrap_err(), msg) {
+                (ParseError::User { error: Error::LitParse { msg, span, .. } }, Some(expected)) => {
+                    assert_eq!(msg, expected);
+                    assert_eq!(span, 0..src.len());
+                }
+                (ParseError::User { .. }, None) => panic!("Unexpected error for {src}"),
+                (_, msg) => assert!(msg.is_none(), "Expected literal error for {src}"),
+            }
+        }
+    }
+
+    #[test]
+    fn variables() {
+        let parser = StmtParser::new();
+        let mut env = Env::default();
+        let mut exec = |s: &str| exec_stmt(&parser.parse(s).unwrap(), &mut env).map(|v| v.get::<u32>());
+
+        assert_eq!(exec("base = 0x40000000").unwrap(), 0x4000_0000);
+        assert_eq!(exec("off = base + 0x18").unwrap(), 0x4000_0018);
+        assert_eq!(exec("off - base").unwrap(), 0x18);
+        assert_eq!(exec("base = base << 1").unwrap(), 0x8000_0000);
+        assert_eq!(exec("off").unwrap(), 0x4000_0018);
+        assert_eq!(exec("_tmp1 = 3").unwrap(), 3);
+        assert_matches!(exec("nope + 1"), Err(EvalErr::Undefined(name)) if name == "nope");
+
+        parser.parse("1 = 2").unwrap_err();
+        parser.parse("x = ").unwrap_err();
+        parser.parse("x = y = 2").unwrap_err();
+    }
+
+
+    #[test]
+    fn history() {
+        let parser = StmtParser::new();
+        let mut env = Env::new(Options { default_type: IntType::new(true, 16), ..Default::default() });
+        let mut exec = |s: &str| {
+            let val = exec_stmt(&parser.parse(s).unwrap(), &mut env)?;
+            env.push_result(val);
+            Ok(val.get::<i16>())
+        };
+
+        assert_matches!(exec("ans"), Err(EvalErr::NoAns));
+        assert_matches!(exec("$1"), Err(EvalErr::NoResult(1)));
+        assert_eq!(exec("5").unwrap(), 5);
+        assert_eq!(exec("ans * 2").unwrap(), 10);
+        assert_eq!(exec("_ - 1").unwrap(), 9);
+        assert_eq!(exec("x = $1 - $3").unwrap(), -4);
+        assert_eq!(exec("$4 + ans").unwrap(), -8);
+        assert_matches!(exec("$0"), Err(EvalErr::NoResult(0)));
+        assert_matches!(exec("$6"), Err(EvalErr::NoResult(6)));
+
+        parser.parse("ans = 1").unwrap_err();
+        parser.parse("$1 = 1").unwrap_err();
+    }
+
+
+    #[test]
+    fn cast() {
+        let parser = StmtParser::new();
+        let mut env = Env::default();
+        let mut exec = |s: &str| exec_stmt(&parser.parse(s).unwrap(), &mut env);
+
+        assert_eq!(exec("0x1234 as u8").unwrap(), Value::new(IntType::new(false, 8), 0x34u8));
+        assert_eq!(exec("0xff as i8").unwrap(), Value::new(IntType::new(true, 8), -1i8));
+        assert_eq!(exec("(0x1ff as u8) as i32").unwrap(), Value::new(IntType::new(true, 32), 0xffi32));
+        assert_eq!(exec("(0x1ff as i8) as i32").unwrap(), Value::new(IntType::new(true, 32), -1i32));
+        assert_eq!(exec("(0x1ff as i8) as u16").unwrap(), Value::new(IntType::new(false, 16), 0xffffu16));
+        assert_eq!(exec("0x80 as i8 as i64 as u32").unwrap(), Value::new(IntType::U32, 0xffff_ff80u32));
+        assert_eq!(exec("-1 as u8").unwrap(), Value::new(IntType::new(false, 8), 0xffu8));
+        assert_eq!(exec("-(1 as u8)").unwrap(), Value::new(IntType::new(false, 8), 0xffu8));
+
+        // Untyped literals take their type from the other operand.
+        assert_eq!(exec("x = 200 as u8").unwrap(), Value::new(IntType::new(false, 8), 200u8));
+        assert_eq!(exec("x + 100").unwrap(), Value::new(IntType::new(false, 8), 44u8));
+        assert_eq!(exec("2 * x").unwrap(), Value::new(IntType::new(false, 8), 144u8));
+        assert_matches!(exec("x + 300"), Err(EvalErr::Invalid(n)) if n == "300");
+        assert_matches!(
+            exec("x + (1 as u16)"),
+            Err(EvalErr::TypeMismatch(IntType { signed: false, bits: 8 }, IntType { signed: false, bits: 16 }))
+        );
+        assert_eq!(exec("(x as u16) + 300").unwrap(), Value::new(IntType::new(false, 16), 500u16));
+
+        // Shift amounts can be of any type.
+        assert_eq!(exec("x >> (4 as i64)").unwrap(), Value::new(IntType::new(false, 8), 12u8));
+        assert_eq!(exec("(1 as i64) << x").unwrap(), Value::new(IntType::new(true, 64), 1i64 << (200 % 64)));
+        assert_eq!(exec("(1 as u16) << (-1 as i8)").unwrap(), Value::new(IntType::new(false, 16), 1u16 << 15));
+
+        parser.parse("1 as").unwrap_err();
+        parser.parse("1 as u0").unwrap_err();
+        parser.parse("1 as i129").unwrap_err();
+    }
+
+    #[test]
+    fn logical() {
+        assert_eq!(eval::<u8>("1 < 2"), 1);
+        assert_eq!(eval::<u8>("2 < 2"), 0);
+        assert_eq!(eval::<u8>("2 <= 2"), 1);
+        assert_eq!(eval::<u8>("3 > 2"), 1);
+        assert_eq!(eval::<u8>("2 >= 3"), 0);
+        assert_eq!(eval::<u8>("2 == 2"), 1);
+        assert_eq!(eval::<u8>("2 != 2"), 0);
+        assert_eq!(eval::<u8>("!0"), 1);
+        assert_eq!(eval::<u8>("!7"), 0);
+        assert_eq!(eval::<u8>("1 && 2"), 1);
+        assert_eq!(eval::<u8>("1 && 0"), 0);
+        assert_eq!(eval::<u8>("0 || 2"), 1);
+        assert_eq!(eval::<u8>("0 || 0"), 0);
+        assert_eq!(eval::<u8>("1 ? 2 : 3"), 2);
+        assert_eq!(eval::<u8>("0 ? 2 : 3"), 3);
+        assert_eq!(eval::<u8>("0 ? 1 : 0 ? 2 : 3"), 3);
+        assert_eq!(eval::<u8>("1 | 2 == 2"), 1);
+        assert_eq!(eval::<u8>("1 + 1 == 2 && 3 < 4 ? 5 : 6"), 5);
+
+        // Signedness comes from the type of the operands.
+        assert_eq!(eval::<u8>("0 - 1 < 0"), 0);
+        assert_eq!(eval::<i8>("0 - 1 < 0"), 1);
+        assert_eq!(eval::<u8>("(-1 as i8) < (0 as i8)"), 1);
+        assert_eq!(eval::<i8>("(-1 as u8) < (0 as u8)"), 0);
+        assert_eq!(eval::<u8>("(-1 as i12) < 0"), 1);
+        assert_eq!(eval::<u8>("(0x800 as i12) < (0x7ff as i12)"), 1);
+        assert_eq!(eval::<u8>("-(1 as i128) < 0"), 1);
+        assert_eq!(eval::<u8>("(-1 as u128) > 0"), 1);
+
+        // Only the side that's needed is evaluated.
+        assert_eq!(eval::<u8>("0 && 1 / 0"), 0);
+        assert_eq!(eval::<u8>("1 || 1 / 0"), 1);
+        assert_eq!(eval::<u8>("1 ? 2 : 1 / 0"), 2);
+        assert_eq!(eval::<u8>("0 ? 1 / 0 : 3"), 3);
+
+        let parser = StmtParser::new();
+        let mut env = Env::default();
+        let mut exec = |s: &str| exec_stmt(&parser.parse(s).unwrap(), &mut env);
+
+        // Results are untyped.
+        assert_eq!(exec("(1 as u8) + ((1 as i64) < (2 as i64))").unwrap(), Value::new(IntType::new(false, 8), 2u8));
+        assert_eq!(exec("(1 as i64) == 1").unwrap(), Value::new(IntType::U32, 1u32));
+        assert_eq!(exec("(1 as u16) && (0 as i8)").unwrap(), Value::new(IntType::U32, 0u32));
+        assert_eq!(exec("(0 as u16) ? 1 : (2 as i16)").unwrap(), Value::new(IntType::new(true, 16), 2i16));
+        assert_matches!(
+            exec("(1 as u8) < (1 as u16)"),
+            Err(EvalErr::TypeMismatch(IntType { signed: false, bits: 8 }, IntType { signed: false, bits: 16 }))
+        );
+        assert_matches!(
+            exec("1 ? (1 as u8) : (1 as u16)"),
+            Err(EvalErr::TypeMismatch(IntType { signed: false, bits: 8 }, IntType { signed: false, bits: 16 }))
+        );
+    }
+
+    #[test]
+    fn call() {
+        assert_eq!(eval::<u32>("popcnt(0xf0f)"), 8);
+        assert_eq!(eval::<u32>("clz(1) + ctz(8)"), 34);
+        assert_eq!(eval::<u16>("bswap(0x1234)"), 0x3412);
+        assert_eq!(eval::<u8>("bitrev(popcnt(3))"), 0x40);
+        assert_eq!(eval::<i8>("cls(-2)"), 6);
+        assert_eq!(eval::<u8>("parity(0x80 | 1)"), 0);
+        assert_eq!(eval::<u8>("mulhu((0xff), 0xff)"), 0xfe);
+
+        let parser = StmtParser::new();
+        let mut env = Env::default();
+        let mut exec = |s: &str| exec_stmt(&parser.parse(s).unwrap(), &mut env);
+        assert_eq!(exec("clz(1 as u12)").unwrap(), Value::new(IntType::new(false, 12), 11u16));
+        assert_eq!(exec("bswap(0x123456 as i24)").unwrap(), Value::new(IntType::new(true, 24), 0x563412i32));
+        assert_matches!(exec("bswap(1 as u12)"), Err(EvalErr::Unsupported(_, typ)) if typ == IntType::new(false, 12));
+        assert_matches!(
+            exec("mulh(1 as u8, 1 as i8)"),
+            Err(EvalErr::TypeMismatch(IntType { signed: false, bits: 8 }, IntType { signed: true, bits: 8 }))
+        );
+
+        assert_matches!(
+            parser.parse("popcnt(1, 2)").unwrap_err(),
+            ParseError::User { error: Error::Arity { name, arity: 1, given: 2, span } }
+                if name == "popcnt" && span == (0..12)
+        );
+        assert_matches!(
+            parser.parse("1 + mulh()").unwrap_err(),
+            ParseError::User { error: Error::Arity { name, arity: 2, given: 0, span } }
+                if name == "mulh" && span == (4..10)
+        );
+    }
+
+    #[test]
+    fn user_func() {
+        let parser = StmtParser::new();
+        let mut env = Env::new(Options { default_type: IntType::new(false, 16), ..Default::default() });
+        let mut exec = |s: &str| parser.parse(s).unwrap().exec_flags(&mut env).map(|res| res.map(|(val, _)| val)).map_err(unlocated);
+        let u16_val = |v: u16| Some(Value::new(IntType::new(false, 16), v));
+
+        assert_eq!(exec("def field(x, hi, lo) = (x >> lo) & ((1 << (hi - lo + 1)) - 1)").unwrap(), None);
+        assert_eq!(exec("field(0xabcd, 11, 4)").unwrap(), u16_val(0xbc));
+        assert_eq!(exec("field(0x1234 as u8, 7, 4)").unwrap(), Some(Value::new(IntType::new(false, 8), 3u8)));
+
+        // Untyped arguments take their type from the body.
+        assert_eq!(exec("def add(a, b) = a + b").unwrap(), None);
+        assert_eq!(exec("add(200 as u8, 100)").unwrap(), Some(Value::new(IntType::new(false, 8), 44u8)));
+        assert_matches!(exec("add(1 as u8, 1 as i8)"), Err(EvalErr::TypeMismatch(IntType { signed: false, bits: 8 }, IntType { signed: true, bits: 8 })));
+
+        // Parameters shadow variables; other names are looked up when called.
+        assert_eq!(exec("x = 5").unwrap(), u16_val(5));
+        assert_eq!(exec("def scale(x) = x * k").unwrap(), None);
+        assert_matches!(exec("scale(2)"), Err(EvalErr::Undefined(k)) if k == "k");
+        assert_eq!(exec("k = 3").unwrap(), u16_val(3));
+        assert_eq!(exec("scale(x + 1)").unwrap(), u16_val(18));
+        assert_eq!(exec("scale(add(x, 1))").unwrap(), u16_val(18));
+
+        // Functions can be redefined, and can call others (including later
+        // definitions).
+        assert_eq!(exec("def twice(x) = add(x, x)").unwrap(), None);
+        assert_eq!(exec("def add(a, b) = a - b").unwrap(), None);
+        assert_eq!(exec("twice(7)").unwrap(), u16_val(0));
+
+        // Recursion, limited in depth.
+        assert_eq!(exec("def fact(n) = n ? n * fact(n - 1) : 1").unwrap(), None);
+        assert_eq!(exec("fact(5)").unwrap(), u16_val(120));
+        assert_eq!(exec("fact(5 as u64)").unwrap(), Some(Value::new(IntType::new(false, 64), 120u64)));
+        assert_eq!(exec("def fib(n) = n < 2 ? n : fib(n - 1) + fib(n - 2)").unwrap(), None);
+        assert_eq!(exec("fib(10 as u8)").unwrap(), Some(Value::new(IntType::new(false, 8), 55u8)));
+
+        // Arguments of recursive calls must agree with the outer call's.
+        assert_eq!(exec("def down(n) = n ? down(n - (1 as i8)) : n").unwrap(), None);
+        assert_eq!(exec("down(3 as i8)").unwrap(), Some(Value::new(IntType::new(true, 8), 0i8)));
+        assert_matches!(
+            exec("down(3 as u8)"),
+            Err(EvalErr::TypeMismatch(IntType { signed: false, bits: 8 }, IntType { signed: true, bits: 8 }))
+        );
+        assert_eq!(exec("def mixed(n) = n ? mixed(n as u8) : 0").unwrap(), None);
+        assert_matches!(
+            exec("mixed(1 as i8)"),
+            Err(EvalErr::TypeMismatch(IntType { signed: false, bits: 8 }, IntType { signed: true, bits: 8 }))
+        );
+        assert_eq!(exec("def short(n) = n ? short() : 0").unwrap(), None);
+        assert_matches!(exec("short(1)"), Err(EvalErr::Arity(f, 1, 0)) if f == "short");
+
+        // Calls in the arguments aren't recursion, so they have a type.
+        assert_eq!(exec("def d(x) = x + x").unwrap(), None);
+        assert_eq!(exec("d(d(200 as u8))").unwrap(), Some(Value::new(IntType::new(false, 8), 32u8)));
+        assert_eq!(exec("def add(a, b) = a + b").unwrap(), None);
+        assert_eq!(exec("add(add(255 as u8, 1), 0)").unwrap(), Some(Value::new(IntType::new(false, 8), 0u8)));
+        assert_matches!(
+            exec("add(add(1 as u8, 1), 1 as i8)"),
+            Err(EvalErr::TypeMismatch(IntType { signed: false, bits: 8 }, IntType { signed: true, bits: 8 }))
+        );
+
+        // Each argument is evaluated once, however often its parameter is
+        // used, so nesting is linear rather than exponential.
+        let nested = format!("{}1 as u64{}", "d(".repeat(30), ")".repeat(30));
+        assert_eq!(exec(&nested).unwrap(), Some(Value::new(IntType::new(false, 64), 1u64 << 30)));
+        assert_eq!(exec("def neg(x) = -x").unwrap(), None);
+        assert_eq!(exec("neg(128) + (0 as i8)").unwrap(), Some(Value::new(IntType::new(true, 8), -128i8)));
+
+        assert_matches!(exec("nope(1)"), Err(EvalErr::UndefinedFunction(f)) if f == "nope");
+        assert_matches!(exec("field(1, 2)"), Err(EvalErr::Arity(f, 3, 2)) if f == "field");
+
+        assert_matches!(
+            parser.parse("def popcnt(x) = x").unwrap_err(),
+            ParseError::User { error: Error::BuiltinName(f) } if f == "popcnt"
+        );
+        assert_matches!(
+            parser.parse("def f(a, b, a) = a").unwrap_err(),
+            ParseError::User { error: Error::DuplicateParam(p) } if p == "a"
+        );
+        parser.parse("def f(1) = 1").unwrap_err();
+        parser.parse("def = 1").unwrap_err();
+        parser.parse("def(x) = 1").unwrap_err();
+        parser.parse("def f() = 1").unwrap();
+    }
+
+    #[test]
+    fn call_depth() {
+        // Calls nested `MAX_CALL_DEPTH` deep need more stack than a test
+        // thread has by default, at least in debug builds.
+        let thread = std::thread::Builder::new().stack_size(64 << 20).spawn(|| {
+            let parser = StmtParser::new();
+            let mut env = Env::default();
+            let mut exec = |s: &str| parser.parse(s).unwrap().exec(&mut env).map_err(unlocated);
+
+            assert_eq!(exec("def fact(n) = n ? n * fact(n - 1) : 1").unwrap(), None);
+            assert_matches!(exec("fact(255)"), Ok(Some(_)));
+            assert_matches!(exec("fact(256)"), Err(EvalErr::CallDepth(f)) if f == "fact");
+            assert_eq!(exec("def forever(x) = forever(x)").unwrap(), None);
+            assert_matches!(exec("forever(1)"), Err(EvalErr::CallDepth(f)) if f == "forever");
+            // The depth is reset after an error.
+            assert_matches!(exec("fact(3)"), Ok(Some(_)));
+        });
+        thread.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn rotate_shift() {
+        assert_eq!(eval::<u8>("0x81 rol 1"), 0x03);
+        assert_eq!(eval::<u8>("0x81 ror 1"), 0xc0);
+        assert_eq!(eval::<u8>("0x81 rol 0"), 0x81);
+        assert_eq!(eval::<u8>("0x81 rol 8"), 0x81);
+        assert_eq!(eval::<u8>("0x81 rol -1"), 0xc0);
+        assert_eq!(eval::<i8>("-128 ror 4"), 0x08);
+        assert_eq!(eval::<u32>("0x12345678 rol 8"), 0x34567812);
+        assert_eq!(eval::<u32>("0x12345678 ror 8"), 0x78123456);
+        assert_eq!(eval::<u128>("1 ror 1"), 1 << 127);
+        assert_eq!(eval::<i128>("(1 << 127) rol 1"), 1);
+
+        // Explicit shifts, regardless of signedness.
+        assert_eq!(eval::<u8>("0x80 asr 3"), 0xf0);
+        assert_eq!(eval::<u8>("0x40 asr 3"), 0x08);
+        assert_eq!(eval::<i8>("-128 lsr 3"), 0x10);
+        assert_eq!(eval::<i8>("-128 asr 3"), -16);
+        assert_eq!(eval::<i128>("-1 lsr 127"), 1);
+        assert_eq!(eval::<u128>("(1 << 127) asr 127"), u128::MAX);
+
+        let parser = StmtParser::new();
+        let mut env = Env::default();
+        let mut exec = |s: &str| exec_stmt(&parser.parse(s).unwrap(), &mut env);
+        assert_eq!(exec("0x801 as u12 rol 4").unwrap(), Value::new(IntType::new(false, 12), 0x018u16));
+        assert_eq!(exec("0x801 as u12 ror 4").unwrap(), Value::new(IntType::new(false, 12), 0x180u16));
+        assert_eq!(exec("0x800 as u12 asr (4 as i8)").unwrap(), Value::new(IntType::new(false, 12), 0xf80u16));
+        assert_eq!(exec("-(1 as i12) lsr 4").unwrap(), Value::new(IntType::new(true, 12), 0xffi16));
+    }
+
+    #[test]
+    fn error_span() {
+        let parser = StmtParser::new();
+        let mut env = Env::default();
+        parser.parse("def f(x) = x / 0").unwrap().exec_flags(&mut env).unwrap();
+        // The part of the input the error is in, if any.
+        let mut span = |s: &str| {
+            let e = parser.parse(s).unwrap().exec_flags(&mut env).unwrap_err();
+            e.span().map(|span| s[span].to_owned())
+        };
+        assert_eq!(span("1 + 2 / (3 - 3)"), Some("2 / (3 - 3)".to_owned()));
+        assert_eq!(span("(1 + 0x1ffu8) * 2"), Some("0x1ffu8".to_owned()));
+        assert_eq!(span("1 + (2 as u8 + 3 as u16)"), Some("2 as u8 + 3 as u16".to_owned()));
+        assert_eq!(span("1 + x"), Some("x".to_owned()));
+        assert_eq!(span("y = 1 + $9"), Some("$9".to_owned()));
+        assert_eq!(span("2 * wmul(1, 2)"), Some("wmul(1, 2)".to_owned()));
+        assert_eq!(span("ctz(b\"toolong\" as u8)"), Some("b\"toolong\"".to_owned()));
+        assert_eq!(span("0xff[40:0]"), Some("0xff[40:0]".to_owned()));
+        assert_eq!(span("(0 as u4)[1:0] = 7"), Some("(0 as u4)[1:0] = 7".to_owned()));
+        assert_eq!(span("(0 as u4)[1:y] = 0"), Some("y".to_owned()));
+
+        // Errors in a function's body are at the call.
+        assert_eq!(span("3 * f(1 + 1)"), Some("f(1 + 1)".to_owned()));
+        assert_eq!(span("3 * f(1 + 1 as u8 + 1 as i8)"), Some("1 + 1 as u8 + 1 as i8".to_owned()));
+        assert_eq!(span("3 * g(1)"), Some("g(1)".to_owned()));
+    }
+
+    #[test]
+    fn wide_mul_div() {
+        let parser = StmtParser::new();
+        let mut env = Env::new(Options { default_type: IntType::new(false, 8), ..Default::default() });
+        let mut exec = |s: &str| parser.parse(s).unwrap().exec_flags(&mut env).map(Option::unwrap).map_err(unlocated);
+        let pair = |hi: i128, lo: i128, typ: IntType| {
+            with_int_type!(typ, T => Value::pair::<T>(typ, T::from_i128_as(hi), T::from_i128_as(lo)))
+        };
+
+        assert_eq!(exec("wmul(200, 200)").unwrap().0, pair(0x9c, 0x40, IntType::new(false, 8)));
+        let opts = Options { default_type: IntType::new(false, 8), ..Default::default() };
+        assert_eq!(run("wmul(200, 200)", opts).unwrap(), pair(0x9c, 0x40, IntType::new(false, 8)));
+        assert_eq!(exec("wmul(-1 as i8, 2 as i8)").unwrap().0, pair(-1, -2, IntType::new(true, 8)));
+        assert_eq!(exec("wmul(0x80 as i8, 0x80 as i8)").unwrap().0, pair(0x40, 0, IntType::new(true, 8)));
+        assert_eq!(
+            exec("wmul(-(1 as u128), -(1 as u128))").unwrap().0,
+            pair(-2, 1, IntType::new(false, 128))
+        );
+        assert_eq!(exec("wmul(-(1 as u12), -(1 as u12))").unwrap().0, pair(0xffe, 1, IntType::new(false, 12)));
+
+        assert_eq!(exec("wdiv(0x9c, 0x41, 200)").unwrap().0, pair(1, 200, IntType::new(false, 8)));
+        assert_eq!(exec("wdiv(-1 as i8, -7 as i8, 2 as i8)").unwrap().0, pair(-1, -3, IntType::new(true, 8)));
+        assert_matches!(exec("wdiv(1, 0, 1)"), Err(EvalErr::DivOverflow(_)));
+        assert_matches!(exec("wdiv(0, 1, 0)"), Err(EvalErr::DivByZero(_)));
+
+        assert_eq!(exec("mulhu(200, 200)").unwrap().0, Value::new(IntType::new(false, 8), 0x9cu8));
+        assert_eq!(exec("mulh(0xff, 2)").unwrap().0, Value::new(IntType::new(false, 8), 0xffu8));
+        assert_eq!(exec("mulhsu(0xff, 0xff)").unwrap().0, Value::new(IntType::new(false, 8), 0xffu8));
+        assert_eq!(exec("mulhu(0xff, 0xff) + 1").unwrap().0, Value::new(IntType::new(false, 8), 0xffu8));
+
+        // Pairs can be stored, but not used as operands.
+        assert_matches!(exec("wmul(1, 2) + 1"), Err(EvalErr::Pair(_)));
+        assert_eq!(exec("p = wmul(16, 16)").unwrap().0, pair(1, 0, IntType::new(false, 8)));
+        assert_eq!(exec("p").unwrap().0, pair(1, 0, IntType::new(false, 8)));
+        assert_matches!(exec("p + 1"), Err(EvalErr::Pair(p)) if p == "p");
+
+        let flags = |res: Result<(Value, Flags), EvalErr>| res.unwrap().1.to_string();
+        assert_eq!(flags(exec("wmul(16, 15)")), "N=0 Z=0 C=0 V=0");
+        assert_eq!(flags(exec("wmul(16, 16)")), "N=0 Z=0 C=1 V=1");
+        assert_eq!(flags(exec("wmul(-1 as i8, 2 as i8)")), "N=1 Z=0 C=0 V=0");
+        assert_eq!(flags(exec("wmul(0, 2)")), "N=0 Z=1 C=0 V=0");
+        assert_eq!(flags(exec("wdiv(0, 0, 2)")), "N=0 Z=1 C=0 V=0");
     }
 
 
@@ -177,7 +2538,7 @@ mod tests {
         ($typ:ty) => {
             assert_eq!(eval::<$typ>(&format!("{} >> 1", (1 as $typ) << (<$typ>::BITS - 1))), (1 << (<$typ>::BITS - 1)) >> 1);
             assert_eq!(eval::<$typ>(&format!("{} >> 1", <$typ>::MAX)), <$typ>::MAX.wrapping_shr(1));
-            assert_eq!(eval::<$typ>("237 + !237 + 1"), 0);
+            assert_eq!(eval::<$typ>("237 + ~237 + 1"), 0);
         }
     }
 
@@ -187,6 +2548,7 @@ mod tests {
         unsigned_tests!(u16);
         unsigned_tests!(u32);
         unsigned_tests!(u64);
+        unsigned_tests!(u128);
     }
 
     macro_rules! signed_tests {
@@ -214,6 +2576,7 @@ mod tests {
         signed_tests!(i16);
         signed_tests!(i32);
         signed_tests!(i64);
+        signed_tests!(i128);
     }
 }
 
@@ -1,206 +1,877 @@
 
-#![feature(assert_matches)]
 #![feature(trait_alias)]
 
+mod builtin;
 mod expr;
+mod printf;
 mod traits;
+mod wide;
 
+use std::fmt;
+use std::ops::Range;
 use std::process::ExitCode;
+use std::str::FromStr;
 use std::thread_local;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 
-use lalrpop_util::lalrpop_mod;
-lalrpop_mod!(grammar, "/grammar.rs");
+use lalrpop_util::{lalrpop_mod, ParseError};
+lalrpop_mod!(#[allow(clippy::all)] grammar, "/grammar.rs");
 use traits::Int;
+use expr::{DivMode, Endian, Env, EvalErr, Flags, IntType, Options, OverflowMode, Value, with_int_type};
+use printf::Template;
 
 use rustyline::{DefaultEditor, error::ReadlineError};
 use clap::{Parser, ValueEnum};
 use num_traits::int::PrimInt;
 
+/// Maximum number of bits in a row of hex/oct or binary output.
+const MAX_ROW_BITS: u32 = 64;
+
 #[inline]
 fn div_round_up<T: PrimInt>(dividend: T, divisor: T) -> T {
     (dividend + divisor - T::one()) / divisor
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, strum::Display)]
-#[strum(serialize_all = "kebab-case")]
+/// Base for output. Parsed from its name, or any radix from 2 to 36.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Base {
+    Dec,
     Hex,
     Oct,
+    Bin,
+    /// Any other radix, from 2 to 36.
+    Radix(u32),
 }
 
 impl Base {
-    fn bits(self) -> u32 {
+    fn from_radix(radix: u32) -> Self {
+        match radix {
+            10 => Base::Dec,
+            16 => Base::Hex,
+            8 => Base::Oct,
+            2 => Base::Bin,
+            _ => Base::Radix(radix),
+        }
+    }
+
+    fn radix(self) -> u32 {
         match self {
-            Self::Hex => 4,
-            Self::Oct => 3,
+            Base::Dec => 10,
+            Base::Hex => 16,
+            Base::Oct => 8,
+            Base::Bin => 2,
+            Base::Radix(radix) => radix,
         }
     }
 
+    /// Bits per digit, if digits are a whole number of bits (i.e., the radix
+    /// is a power of two).
+    fn bits(self) -> Option<u32> {
+        let radix = self.radix();
+        radix.is_power_of_two().then(|| radix.trailing_zeros())
+    }
 
-    fn subscript(self) -> &'static str {
-         match self {
-            Base::Oct => "₈", 
-            Base::Hex => "₁₆",
+    /// Number of digits per group when digits are separated: binary by hex
+    /// digit, hex by 16-bit halfword, and anything else by thousands.
+    fn group_len(self) -> usize {
+        let hex_bits = Base::Hex.bits().unwrap() as usize;
+        match self {
+            Base::Bin => hex_bits,
+            Base::Hex => 16 / hex_bits,
+            _ => 3,
+        }
+    }
+
+    fn subscript(self) -> String {
+        self.radix().to_string().chars()
+            .map(|ch| char::from_u32('₀' as u32 + ch.to_digit(10).unwrap()).unwrap())
+            .collect()
+    }
+
+    /// The ASCII prefix for this base, as for literals, e.g. `0x`, or nothing
+    /// for decimal.
+    fn prefix(self) -> String {
+        match self {
+            Base::Dec => String::new(),
+            Base::Hex => "0x".to_owned(),
+            Base::Oct => "0o".to_owned(),
+            Base::Bin => "0b".to_owned(),
+            Base::Radix(radix) => format!("0r{radix}_"),
+        }
+    }
+
+    /// The ASCII suffix for this base, as in assembly, e.g. `h`.
+    fn suffix(self) -> String {
+        match self {
+            Base::Dec => "d".to_owned(),
+            Base::Hex => "h".to_owned(),
+            Base::Oct => "o".to_owned(),
+            Base::Bin => "b".to_owned(),
+            Base::Radix(radix) => format!("_{radix}"),
+        }
+    }
+
+    /// `val`, which must be zero-extended (unless the base is decimal), in
+    /// this base, with no padding.
+    fn digits<T: Int>(self, val: T) -> String {
+        match self {
+            Base::Dec => format!("{val}"),
+            Base::Hex => format!("{val:X}"),
+            Base::Oct => format!("{val:o}"),
+            Base::Bin => format!("{val:b}"),
+            Base::Radix(radix) => {
+                let radix = T::from_u32(radix).unwrap();
+                let mut val = val;
+                let mut digits = vec![];
+                loop {
+                    digits.push(digit_char((val % radix).to_u32().unwrap()));
+                    val = val / radix;
+                    if val == T::zero() {
+                        break;
+                    }
+                }
+                digits.iter().rev().collect()
+            }
         }
     }
 }
 
-fn write_int<T: Int>(f: &mut impl Write, val: T, base: Base) -> io::Result<()> {
-    writeln!(f, "{val}₁₀")?;
+impl fmt::Display for Base {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Base::Dec => write!(f, "dec"),
+            Base::Hex => write!(f, "hex"),
+            Base::Oct => write!(f, "oct"),
+            Base::Bin => write!(f, "bin"),
+            Base::Radix(radix) => write!(f, "{radix}"),
+        }
+    }
+}
 
-    // Writing the decimal representation, above, is signedness-aware. The rest
-    // of the writing is purely the underlying representation, and doesn't vary
+impl FromStr for Base {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dec" => Ok(Base::Dec),
+            "hex" => Ok(Base::Hex),
+            "oct" => Ok(Base::Oct),
+            "bin" => Ok(Base::Bin),
+            _ => s.parse().ok()
+                .filter(|radix| (2..=36).contains(radix))
+                .map(Base::from_radix)
+                .ok_or_else(|| format!("Invalid base '{s}' (expected dec, hex, oct, bin, or 2 to 36)")),
+        }
+    }
+}
+
+/// Digit `digit` (less than 36), as an uppercase letter if it's over 9.
+fn digit_char(digit: u32) -> char {
+    char::from_digit(digit, 36).unwrap().to_ascii_uppercase()
+}
+
+/// Splits `digits` (ASCII) into groups of `len`, counting from the least
+/// significant, so that only the most significant group may be shorter.
+fn group_digits(digits: &str, len: usize) -> Vec<&str> {
+    digits.as_bytes().rchunks(len).rev()
+        .map(|group| std::str::from_utf8(group).unwrap())
+        .collect()
+}
+
+/// How the base of each line is marked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum Label {
+    /// Unicode subscripts, e.g. `FF₁₆`
+    #[default]
+    #[value(skip)]
+    Subscript,
+    /// Prefixes, e.g. `0xFF`
+    Prefix,
+    /// Suffixes, e.g. `FFh`
+    Suffix,
+}
+
+/// Whether the locale's encoding is UTF-8, going by the first of `LC_ALL`,
+/// `LC_CTYPE`, and `LANG` that's set.
+fn utf8_locale() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"].into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .is_some_and(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+/// How bits are numbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BitOrder {
+    /// The least significant bit is bit 0
+    Lsb0,
+    /// The most significant bit is bit 0, as in PowerPC documentation
+    Msb0,
+}
+
+/// How values are printed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Layout {
+    /// Bases to print, a line (or row of lines) each, in order.
+    show: Vec<Base>,
+    /// How each line's base is marked.
+    label: Label,
+    /// Numbering for a ruler under each binary line, if any.
+    ruler: Option<BitOrder>,
+    /// Whether to color binary (see `color_binary()`).
+    color: bool,
+}
+
+impl Layout {
+    /// The first base shown whose digits are several bits (or else hex), by
+    /// which binary is grouped, and with which it's aligned.
+    fn group(&self) -> Base {
+        self.show.iter().copied()
+            .find(|base| base.bits().is_some_and(|bits| bits > 1))
+            .unwrap_or(Base::Hex)
+    }
+}
+
+impl From<Base> for Layout {
+    /// The default layout, with decimal, `base`, and binary.
+    fn from(base: Base) -> Self {
+        let mut show = vec![Base::Dec, base, Base::Bin];
+        show.dedup();
+        Layout { show, label: Label::Subscript, ruler: None, color: false }
+    }
+}
+
+/// Writes `val`, which is `bits` wide (and sign- or zero-extended to fill `T`),
+/// followed on the first line by `flags`, if given. In color, the bits that
+/// differ from `prev`, if given, are highlighted.
+fn write_int<T: Int>(f: &mut impl Write, val: T, bits: u32, layout: &Layout, prev: Option<T>, flags: Option<Flags>) -> io::Result<()> {
+    // Writing the decimal representation is signedness-aware. The rest of the
+    // writing is purely the underlying representation, and doesn't vary
     // between signed and unsigned. On the other hand, the rest of it needs a
     // logical right shift, so a convertion to unsigned is done. Since the type
     // changes (given Rust's restrictions) its easiest to do the rest in a separate
-    // function.
-    write_int_continue(f, val.as_unsigned(), base)
+    // function. Any sign extension beyond `bits` is masked off.
+    let dec = Base::Dec.digits(val);
+    let unsigned = IntType::new(false, bits);
+    let changed = prev.map_or(0, |prev| unsigned.wrap((val.as_i128() ^ prev.as_i128()) as u128));
+    let lines = int_lines(unsigned.wrap(val.as_unsigned()), bits, T::is_signed(), changed, layout);
+    write_lines(f, lines, &dec, layout.label, flags)
 }
 
-fn write_int_continue<T: Int>(f: & mut impl Write, mut val: T, base: Base) -> io::Result<()> {
-    // For oct and hex, split the binary in digit-sized chunks, and align them.
-
-    let subscript = base.subscript();
-    let digit_bits = T::from_u32(base.bits()).unwrap();
-    let digit_mask = (T::one() << digit_bits.to_usize().unwrap()) - T::one();
-    let mut digits = vec![];
-    while val > T::zero() {
-        digits.push(val & digit_mask);
-        val >>= digit_bits;
+/// Writes `lines` (see `int_lines()`), labeled with their bases, and with `dec`
+/// for decimal lines. `flags`, if given, follows the first line.
+fn write_lines(f: &mut impl Write, lines: Vec<(Option<Base>, String)>, dec: &str, label: Label, flags: Option<Flags>) -> io::Result<()> {
+    // Prefixes are right-aligned, so the lines after them stay aligned, and
+    // rulers are indented to match.
+    let prefix_width = lines.iter()
+        .filter_map(|(base, _)| base.filter(|base| *base != Base::Dec))
+        .map(|base| base.prefix().len())
+        .max()
+        .unwrap_or(0);
+    for (i, (base, line)) in lines.into_iter().enumerate() {
+        let Some(base) = base else {
+            match label {
+                Label::Prefix => writeln!(f, "{:prefix_width$}{line}", "")?,
+                _ => writeln!(f, "{line}")?,
+            }
+            continue;
+        };
+        let line = if base == Base::Dec { dec } else { &line };
+        match label {
+            Label::Subscript => write!(f, "{line}{}", base.subscript())?,
+            Label::Prefix if base == Base::Dec => write!(f, "{line}")?,
+            Label::Prefix => write!(f, "{:>prefix_width$}{line}", base.prefix())?,
+            Label::Suffix => write!(f, "{line}{}", base.suffix())?,
+        }
+        if let (0, Some(flags)) = (i, flags) {
+            write!(f, "  [{flags}]")?;
+        }
+        writeln!(f)?;
     }
+    Ok(())
+}
 
-    // Add extra zero chunks until we reach the full width.
-    let t_bits = T::from(T::zero().count_zeros()).unwrap();
-    let num_chunks = div_round_up(t_bits, digit_bits);
-    while digits.len() < num_chunks.to_usize().unwrap() {
-        digits.push(T::zero());
+/// Lays out `val`, which must be zero-extended, as a line (without subscript)
+/// for each base in `layout`, in order, with an unlabeled ruler after each
+/// binary line if `layout` has one. Decimal lines are left empty, as they
+/// depend on the signedness of the type. In color, binary is colored as for a
+/// `signed` type, with the bits set in `changed` highlighted.
+fn int_lines<T: Int>(val: T, bits: u32, signed: bool, changed: u128, layout: &Layout) -> Vec<(Option<Base>, String)> {
+    let group = layout.group();
+    let aligned = layout.show.contains(&Base::Bin);
+    let mut rows = int_rows(val, bits, group);
+
+    // Rulers for each row's binary, numbered from the bottom row up.
+    let mut rulers = vec![None; rows.len()];
+    if let Some(order) = layout.ruler {
+        let mut low = 0;
+        for ((_, binary), ruler_line) in rows.iter().zip(&mut rulers).rev() {
+            *ruler_line = Some(ruler(binary, low, bits, order));
+            low += binary.chars().filter(|ch| *ch != ' ').count() as u32;
+        }
     }
 
-    // Bits in the most significant chunk (since chunk size may not evenly divide word size).
-    let top_bits = if t_bits % digit_bits == T::zero() { 
-        digit_bits 
-    } else {
-        t_bits % digit_bits
+    // Color the binary, now that the rulers have been laid out from it.
+    if layout.color {
+        let mut high = bits;
+        for (_, binary) in &mut rows {
+            let len = binary.chars().filter(|ch| *ch != ' ').count() as u32;
+            *binary = color_binary(binary, high - 1, bits, signed, changed);
+            high -= len;
+        }
+    }
+    let binary_lines = |lines: &mut Vec<_>, binary: &String, ruler_line: &Option<String>| {
+        lines.push((Some(Base::Bin), binary.clone()));
+        lines.extend(ruler_line.clone().map(|ruler_line| (None, ruler_line)));
     };
 
-    // Write hex/oct, aligned with binary.
-    let mut seen_nonzero = false;
-    for (i, digit) in digits.iter().rev().enumerate() {
-        if i != 0 {
-            write!(f, " ")?;
+    let mut lines = vec![];
+    let mut show = layout.show.iter().copied().peekable();
+    while let Some(base) = show.next() {
+        let next = show.peek().copied();
+        match base {
+            Base::Dec => lines.push((Some(base), String::new())),
+            // Binary and the base it's grouped by have their rows interleaved
+            // if they're adjacent.
+            Base::Bin if next == Some(group) => {
+                show.next();
+                for ((digits, binary), ruler_line) in rows.iter().zip(&rulers) {
+                    binary_lines(&mut lines, binary, ruler_line);
+                    lines.push((Some(group), digits.clone()));
+                }
+            }
+            _ if base == group && next == Some(Base::Bin) => {
+                show.next();
+                for ((digits, binary), ruler_line) in rows.iter().zip(&rulers) {
+                    lines.push((Some(group), digits.clone()));
+                    binary_lines(&mut lines, binary, ruler_line);
+                }
+            }
+            Base::Bin => {
+                for ((_, binary), ruler_line) in rows.iter().zip(&rulers) {
+                    binary_lines(&mut lines, binary, ruler_line);
+                }
+            }
+            _ if base == group && aligned => lines.extend(rows.iter().map(|(digits, _)| (Some(base), digits.clone()))),
+            _ => lines.push((Some(base), base.digits(val))),
+        }
+    }
+    lines
+}
+
+/// A ruler for `binary`, a line of space-separated chunks of bits whose last
+/// is bit `low` of `bits`, numbering (per `order`) the first bit of each chunk
+/// and the last bit, e.g. `31   27 ... 3  0`, where the numbers fit.
+fn ruler(binary: &str, low: u32, bits: u32, order: BitOrder) -> String {
+    let number = |bit: u32| match order {
+        BitOrder::Lsb0 => bit,
+        BitOrder::Msb0 => bits - 1 - bit,
+    };
+    let columns: Vec<char> = binary.chars().collect();
+    let width = columns.len();
+
+    // Place numbers from right to left, each ending at least a space before
+    // the start of the last one placed.
+    let mut ruler = vec![' '; width];
+    let mut limit = width + 1;
+    let mut place = |start: Option<usize>, bit: u32| {
+        let label = number(bit).to_string();
+        if let Some(start) = start.filter(|start| start + label.len() < limit) {
+            ruler.splice(start..start + label.len(), label.chars());
+            limit = start;
         }
+    };
+    place(width.checked_sub(number(low).to_string().len()), low);
 
-        let chunk_width = if i == 0 { top_bits } else { digit_bits }.to_usize().unwrap();
+    let mut bit = low;
+    for (col, ch) in columns.iter().enumerate().rev() {
+        if *ch == ' ' {
+            continue;
+        }
+        if col == 0 || columns[col - 1] == ' ' {
+            place(Some(col), bit);
+        }
+        bit += 1;
+    }
+    ruler.into_iter().collect()
+}
 
-        // Don't write leading zeros for oct/hex.
-        if *digit != T::zero() {
-            seen_nonzero = true;
+/// `binary`, whose first digit is bit `high` of `bits`, with zeros dim, ones
+/// bold, the sign bit red if `signed`, and the bits in `changed` reversed.
+fn color_binary(binary: &str, high: u32, bits: u32, signed: bool, changed: u128) -> String {
+    let mut colored = String::new();
+    let mut style = None;
+    let mut bit = high;
+    for ch in binary.chars() {
+        let digit_style = (ch != ' ').then(|| {
+            let mut sgr = if ch == '1' { "1" } else { "2" }.to_owned();
+            if signed && bit == bits - 1 {
+                sgr += ";31";
+            }
+            if (changed >> bit) & 1 != 0 {
+                sgr += ";7";
+            }
+            bit = bit.wrapping_sub(1);
+            sgr
+        });
+        if digit_style != style {
+            match &digit_style {
+                Some(sgr) => colored += &format!("\x1b[0;{sgr}m"),
+                None => colored += "\x1b[0m",
+            }
+            style = digit_style;
         }
-        if !seen_nonzero && i + 1 != digits.len() {
-            write!(f, "{:chunk_width$}", "")?;
-        } else {
-            match base {
-                Base::Oct => write!(f, "{digit:chunk_width$o}")?,
-                Base::Hex => write!(f, "{digit:chunk_width$X}")?,
+        colored.push(ch);
+    }
+    if style.is_some() {
+        colored += "\x1b[0m";
+    }
+    colored
+}
+
+/// Lays out `val`, which must be zero-extended, as rows of digits in `base`,
+/// which must be a power of two, and aligned binary (without subscripts).
+fn int_rows<T: Int>(val: T, bits: u32, base: Base) -> Vec<(String, String)> {
+    // For oct and hex, split the binary, with leading zeros to the full width,
+    // in digit-sized chunks, and align the digits with them. The most
+    // significant chunk may be short, since the chunk size may not evenly
+    // divide the width.
+    let binary = format!("{val:0width$b}", width = bits as usize);
+    let chunks = group_digits(&binary, base.bits().unwrap() as usize);
+
+    // Types wider than MAX_ROW_BITS are wrapped across several rows, each with
+    // its own hex/oct and binary line. Rows are split between digits, as evenly
+    // as possible, with any short row at the top.
+    let num_rows = div_round_up(bits, MAX_ROW_BITS);
+    let row_chunks = div_round_up(chunks.len(), num_rows as usize);
+
+    let mut seen_nonzero = false;
+    let mut lines = vec![];
+    for row in chunks.rchunks(row_chunks).rev() {
+        let mut digits = vec![];
+        for (i, chunk) in row.iter().enumerate() {
+            // Write hex/oct, aligned with binary. Don't write leading zeros
+            // for oct/hex, except for the last digit of each row, so no row
+            // is blank.
+            let digit = u32::from_str_radix(chunk, 2).unwrap();
+            if digit != 0 {
+                seen_nonzero = true;
             }
+            let ch = if seen_nonzero || i + 1 == row.len() { digit_char(digit) } else { ' ' };
+            digits.push(format!("{ch:>0$}", chunk.len()));
         }
+        lines.push((digits.join(" "), row.join(" ")));
     }
-    writeln!(f, "{subscript}")?;
 
-    // Write binary (including leading zeros).
-    for (i, digit) in digits.iter().rev().enumerate() {
-        if i != 0 {
-            write!(f, " ")?;
+    lines
+}
+
+/// Writes the pair `hi:lo`, each `bits` wide, side by side if they fit, or
+/// else stacked, with `flags`, if given, on the first line.
+fn write_pair<T: Int>(f: &mut impl Write, hi: T, lo: T, bits: u32, layout: &Layout, flags: Option<Flags>) -> io::Result<()> {
+    // See write_int().
+    let dec = format!("{}:{}", Base::Dec.digits(hi), Base::Dec.digits(lo));
+    let unsigned = IntType::new(false, bits);
+    let (hi, lo) = (unsigned.wrap(hi.as_unsigned()), unsigned.wrap(lo.as_unsigned()));
+    let (hi_lines, lo_lines) = (int_lines(hi, bits, T::is_signed(), 0, layout), int_lines(lo, bits, T::is_signed(), 0, layout));
+
+    let lines = if 2 * bits > MAX_ROW_BITS {
+        let dec_line = layout.show.contains(&Base::Dec).then(|| (Some(Base::Dec), String::new()));
+        let not_dec = |lines: Vec<(Option<Base>, String)>| lines.into_iter().filter(|(base, _)| *base != Some(Base::Dec));
+        dec_line.into_iter().chain(not_dec(hi_lines)).chain(not_dec(lo_lines)).collect()
+    } else {
+        hi_lines.into_iter().zip(lo_lines)
+            .map(|((base, hi), (_, lo))| (base, format!("{hi} : {lo}")))
+            .collect()
+    };
+    write_lines(f, lines, &dec, layout.label, flags)
+}
+
+/// When to color output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+enum ColorMode {
+    /// If stdout is a terminal, and `NO_COLOR` isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|val| val.is_empty()),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
         }
-        let chunk_width = if i == 0 { top_bits } else { digit_bits }.to_usize().unwrap();
-        write!(f, "{digit:0chunk_width$b}")?;
     }
-    writeln!(f, "₂")
 }
 
-fn print_int<T: Int>(val: T, base: Base) {
-    let mut stdout = io::stdout().lock();
-    write_int(&mut stdout, val, base).expect("Error printing int");
+/// Format in which values and errors are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+enum Format {
+    /// Lines in each base, aligned for reading
+    Text,
+    /// A JSON object per value or error, on one line, to stdout
+    Json,
+}
+
+/// `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for ch in s.chars() {
+        match ch {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            '\n' => json += "\\n",
+            ch if ch.is_control() => json += &format!("\\u{:04x}", ch as u32),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
 }
 
+/// A JSON object with `fields`, whose values are already JSON.
+fn json_object<K: AsRef<str>>(fields: &[(K, String)]) -> String {
+    let fields: Vec<_> = fields.iter()
+        .map(|(key, val)| format!("{}: {val}", json_string(key.as_ref())))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
 
-#[derive(Debug, Clone, Copy, strum::Display, ValueEnum)]
-#[strum(serialize_all = "kebab_case")]
-enum IntType {
-    U8,
-    U16,
-    U32,
-    U64,
-    I8,
-    I16,
-    I32,
-    I64,
+/// JSON fields for `val`, which is `bits` wide: its value, as signed and
+/// unsigned, and its digits in each base. Numbers are strings, as they may be
+/// too big for a JSON parser's numbers.
+fn json_int_fields<T: Int>(val: T, bits: u32, layout: &Layout) -> Vec<(&'static str, String)> {
+    let unsigned = IntType::new(false, bits).wrap(val.as_i128() as u128);
+    let signed = IntType::new(true, bits).wrap(val.as_i128());
+
+    let mut bases = vec![Base::Dec, Base::Hex, Base::Oct, Base::Bin];
+    for base in &layout.show {
+        if !bases.contains(base) {
+            bases.push(*base);
+        }
+    }
+    let digits: Vec<_> = bases.into_iter()
+        .map(|base| {
+            let digits = if base == Base::Dec { base.digits(val) } else { base.digits(unsigned) };
+            (base.to_string(), json_string(&digits))
+        })
+        .collect();
+
+    vec![
+        ("value", json_string(&val.to_string())),
+        ("signed", json_string(&signed.to_string())),
+        ("unsigned", json_string(&unsigned.to_string())),
+        ("bases", json_object(&digits)),
+    ]
 }
 
+/// Writes `val` as a JSON object, with its number in the history, `n`, and
+/// `flags`, if given. A pair has `hi` and `lo` objects for its halves.
+fn write_json(f: &mut impl Write, val: Value, n: Option<usize>, layout: &Layout, flags: Option<Flags>) -> io::Result<()> {
+    let mut fields = vec![];
+    if let Some(n) = n {
+        fields.push(("n", n.to_string()));
+    }
+    fields.push(("type", json_string(&val.typ.to_string())));
+    with_int_type!(val.typ, T => match val.hi() {
+        Some(hi) => {
+            fields.push(("hi", json_object(&json_int_fields(hi.get::<T>(), val.typ.bits, layout))));
+            fields.push(("lo", json_object(&json_int_fields(val.lo().get::<T>(), val.typ.bits, layout))));
+        }
+        None => fields.extend(json_int_fields(val.get::<T>(), val.typ.bits, layout)),
+    });
+    if let Some(Flags { n, z, c, v }) = flags {
+        fields.push(("flags", json_object(&[("n", n.to_string()), ("z", z.to_string()), ("c", c.to_string()), ("v", v.to_string())])));
+    }
+    writeln!(f, "{}", json_object(&fields))
+}
+
+/// Writes an error as a JSON object, with its `kind` (e.g., "parse"), and its
+/// `span` of bytes in `src`, written in characters, if given.
+fn write_json_error(f: &mut impl Write, kind: &str, msg: &str, src: &str, span: Option<Range<usize>>) -> io::Result<()> {
+    let span = match span {
+        Some(span) => {
+            let start = src[..span.start].chars().count();
+            let end = start + src[span].chars().count();
+            json_object(&[("start", start.to_string()), ("end", end.to_string())])
+        }
+        None => "null".to_owned(),
+    };
+    let error = json_object(&[("kind", json_string(kind)), ("message", json_string(msg)), ("span", span)]);
+    writeln!(f, "{}", json_object(&[("error", error)]))
+}
+
+
 /// Programmer's calculator
 #[derive(Parser, Debug)]
 struct Args {
     /// Expression to evaluate. Leave empty for repl
     expr: Option<String>,
 
-    /// Base in which to print results. Decimal and binary are always printed
+    /// Base in which to print results, along with decimal and binary (unless
+    /// given by --show). One of dec, hex, oct, bin, or a radix from 2 to 36
     #[arg(long, default_value_t=Base::Hex)]
     base: Base,
 
-    /// Type of expression
+    /// Radix in which to print results, from 2 to 36 (the same as --base)
+    #[arg(long, conflicts_with = "base", value_parser = clap::value_parser!(u32).range(2..=36))]
+    radix: Option<u32>,
+
+    /// Comma-separated bases to print, in order, e.g. `hex,bin` [default:
+    /// dec,<BASE>,bin]
+    #[arg(long, value_delimiter = ',')]
+    show: Vec<Base>,
+
+    /// Type of expressions that aren't otherwise typed (e.g., with `as`). One of
+    /// u1 to u128 or i1 to i128
     #[arg(long = "type", default_value_t=IntType::U32)]
     typ: IntType,
+
+    /// Result of division or remainder by zero
+    #[arg(long, default_value_t=DivMode::Error)]
+    div_by_zero: DivMode,
+
+    /// Result of arithmetic that overflows
+    #[arg(long, default_value_t=OverflowMode::Wrap)]
+    overflow: OverflowMode,
+
+    /// Require hex, octal, and binary literals to fit in signed types as
+    /// positive numbers, rather than giving a bit pattern (e.g., 0xff for -1 as
+    /// an i8)
+    #[arg(long)]
+    strict_literals: bool,
+
+    /// Order in which the bytes of byte strings (e.g., b"ELF") are packed
+    #[arg(long, default_value_t=Endian::Big)]
+    endian: Endian,
+
+    /// Print the status flags (negative, zero, carry, overflow) set by each
+    /// expression's top-level operation
+    #[arg(long)]
+    flags: bool,
+
+    /// Format in which results and errors are printed
+    #[arg(long, default_value_t=Format::Text)]
+    format: Format,
+
+    /// Print results with a printf-style template instead, e.g. `0x%08X`.
+    /// Directives are %[flags][width][.precision]conv, with conv one of d
+    /// (decimal), i (signed), u (unsigned), x, X, o, or b, and flags any of #
+    /// (prefix), 0 (zero-pad), - (left-justify), + (sign), or ' (group digits)
+    #[arg(long, conflicts_with = "format")]
+    printf: Option<Template>,
+
+    /// Mark bases with ASCII prefixes (e.g., 0x), or with `=suffix`, suffixes
+    /// (e.g., h), rather than subscripts. The default if the locale isn't
+    /// UTF-8
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "prefix", value_name = "STYLE")]
+    ascii: Option<Label>,
+
+    /// Print a ruler under each binary line, marking bit numbers, with
+    /// `=msb0`, numbered from the most significant bit
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "lsb0", value_name = "ORDER")]
+    ruler: Option<BitOrder>,
+
+    /// When to color binary output: zeros dim, ones bold, and, for signed
+    /// types, the sign bit red
+    #[arg(long, default_value_t=ColorMode::Auto)]
+    color: ColorMode,
+
+    /// In color, highlight the bits of each result in the repl that changed
+    /// from the previous one (of the same type)
+    #[arg(long)]
+    highlight_changes: bool,
 }
 
-macro_rules! eval {
-    ($expr:ident, $base:ident, $typ:ty) => {{
-        let val = match $expr.eval::<$typ>() {
-            Ok(val) => val,
-            Err(e) => { 
-                eprintln!("{e}");
-                return Err(());
-            },
+impl Args {
+    fn layout(&self) -> Layout {
+        let mut layout = if self.show.is_empty() {
+            Layout::from(self.radix.map_or(self.base, Base::from_radix))
+        } else {
+            Layout { show: self.show.clone(), label: Label::Subscript, ruler: None, color: false }
         };
+        layout.label = self.ascii.unwrap_or(if utf8_locale() { Label::Subscript } else { Label::Prefix });
+        layout.ruler = self.ruler;
+        layout.color = self.color.enabled();
+        layout
+    }
+}
+
+/// Prints `val`, labeled with its number `n` in the history, if given. With
+/// `--highlight-changes`, the bits that changed from `prev`, the previous
+/// result, if given, are highlighted.
+fn print_value(val: Value, n: Option<usize>, args: &Args, layout: &Layout, prev: Option<Value>, flags: Option<Flags>) {
+    let mut stdout = io::stdout().lock();
+    if args.format == Format::Json {
+        return write_json(&mut stdout, val, n, layout, flags).expect("Error printing int");
+    }
+
+    let label = n.map_or(String::new(), |n| format!("${n} = "));
+    if let Some(template) = &args.printf {
+        return writeln!(stdout, "{label}{}", template.format(val)).expect("Error printing int");
+    }
+
+    let mut output = vec![];
+    with_int_type!(val.typ, T => match val.hi() {
+        Some(hi) => write_pair(&mut output, hi.get::<T>(), val.lo().get::<T>(), val.typ.bits, layout, flags),
+        None => {
+            let prev = prev.filter(|prev| args.highlight_changes && prev.typ == val.typ && prev.hi().is_none());
+            write_int(&mut output, val.get::<T>(), val.typ.bits, layout, prev.map(|prev| prev.get::<T>()), flags)
+        }
+    }).expect("Error printing int");
+
+    write_labeled(&mut stdout, &label, &String::from_utf8(output).unwrap()).expect("Error printing int");
+}
+
+/// Writes `text`, with `label` before its first line, and the rest of its
+/// lines indented to match, so that they stay aligned with the first.
+fn write_labeled(f: &mut impl Write, label: &str, text: &str) -> io::Result<()> {
+    let indent = " ".repeat(label.chars().count());
+    for (i, line) in text.lines().enumerate() {
+        let prefix = if i == 0 { label } else { &indent };
+        writeln!(f, "{prefix}{line}")?;
+    }
+    Ok(())
+}
 
-        print_int(val, $base);
-    }}
+/// Location of parse error `e` in `src`, if it's about a particular part.
+fn parse_error_span<T>(src: &str, e: &ParseError<usize, T, expr::Error>) -> Option<Range<usize>> {
+    match e {
+        ParseError::InvalidToken { location } => {
+            let len = src[*location..].chars().next().map_or(0, char::len_utf8);
+            Some(*location..*location + len)
+        }
+        ParseError::UnrecognizedEof { location, .. } => Some(*location..*location),
+        ParseError::UnrecognizedToken { token: (l, _, r), .. } | ParseError::ExtraToken { token: (l, _, r) } => Some(*l..*r),
+        ParseError::User { error } => error.span(),
+    }
+}
+
+/// Prints a parse error in `src`, followed by `src` with the part it's about
+/// underlined, if it's about a particular part.
+fn print_parse_error<T: std::fmt::Display>(src: &str, e: &ParseError<usize, T, expr::Error>, args: &Args) {
+    if args.format == Format::Json {
+        let span = parse_error_span(src, e);
+        return write_json_error(&mut io::stdout().lock(), "parse", &e.to_string(), src, span)
+            .expect("Error printing error");
+    }
+
+    eprintln!("{e}");
+    if let ParseError::User { error } = e {
+        if let Some(span) = error.span() {
+            print_underlined(src, span);
+        }
+    }
+}
+
+/// Prints an evaluation error in `src`, followed by `src` with the
+/// subexpression it's about underlined, if it's about a particular one.
+fn print_eval_error(src: &str, e: &EvalErr, args: &Args) {
+    if args.format == Format::Json {
+        return write_json_error(&mut io::stdout().lock(), "eval", &e.to_string(), src, e.span())
+            .expect("Error printing error");
+    }
+
+    eprintln!("{e}");
+    if let Some(span) = e.span() {
+        print_underlined(src, span);
+    }
+}
+
+/// Prints `src`, with the part at `span` underlined.
+fn print_underlined(src: &str, span: Range<usize>) {
+    let indent = src[..span.start].chars().count();
+    let len = src[span].chars().count().max(1);
+    eprintln!("{src}");
+    eprintln!("{}{}", " ".repeat(indent), "^".repeat(len));
+}
+
+/// Prints an error of kind `kind` (e.g., "command"), which isn't about a
+/// particular part of the input.
+fn print_error(kind: &str, msg: &str, args: &Args) {
+    match args.format {
+        Format::Text => eprintln!("{msg}"),
+        Format::Json => write_json_error(&mut io::stdout().lock(), kind, msg, "", None)
+            .expect("Error printing error"),
+    }
 }
 
-fn exec(expr: &str, base: Base, typ: IntType) -> Result<(), ()> {
+/// Parses and executes `stmt`, printing the result. If `numbered`, the result
+/// is labeled with its number in the history.
+fn exec(stmt: &str, env: &mut Env, args: &Args, layout: &Layout, numbered: bool) -> Result<(), ()> {
     thread_local! {
-        static PARSER: grammar::ExprParser = Default::default();
+        static PARSER: grammar::StmtParser = Default::default();
     }
 
-    let expr = match PARSER.with(|p| p.parse(expr)) {
-        Ok(expr) => expr,
+    let src = stmt;
+    let stmt = match PARSER.with(|p| p.parse(stmt)) {
+        Ok(stmt) => stmt,
         Err(e) => { 
-            eprintln!("{e}");
+            print_parse_error(src, &e, args);
             return Err(());
         },
     };
 
-    use IntType::*;
-    match typ {
-        U8 => eval!(expr, base, u8),
-        U16 => eval!(expr, base, u16),
-        U32 => eval!(expr, base, u32),
-        U64 => eval!(expr, base, u64),
-        I8 => eval!(expr, base, i8),
-        I16 => eval!(expr, base, i16),
-        I32 => eval!(expr, base, i32),
-        I64 => eval!(expr, base, i64),
-    }
+    // Flags are only computed if they're shown.
+    let res = if args.flags {
+        stmt.exec_flags(env).map(|res| res.map(|(val, flags)| (val, Some(flags))))
+    } else {
+        stmt.exec(env).map(|res| res.map(|val| (val, None)))
+    };
+    let (val, flags) = match res {
+        Ok(Some(res)) => res,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            print_eval_error(src, &e, args);
+            return Err(());
+        },
+    };
 
+    let prev = env.last_result();
+    let n = env.push_result(val);
+    print_value(val, numbered.then_some(n), args, layout, prev, flags);
     Ok(())
 }
 
+fn print_history(env: &Env, args: &Args, layout: &Layout) {
+    let mut prev = None;
+    for (n, val) in env.results() {
+        print_value(val, Some(n), args, layout, prev, None);
+        prev = Some(val);
+    }
+}
+
+/// Runs a repl command (a line starting with ':').
+fn command(cmd: &str, env: &Env, args: &Args, layout: &Layout) {
+    match cmd.trim() {
+        "history" => print_history(env, args, layout),
+        cmd => print_error("command", &format!("Unknown command ':{cmd}'"), args),
+    }
+}
+
 fn main() -> ExitCode {
 
     let args = Args::parse();
 
+    let mut env = Env::new(Options {
+        default_type: args.typ,
+        div_mode: args.div_by_zero,
+        overflow: args.overflow,
+        strict_literals: args.strict_literals,
+        endian: args.endian,
+    });
+
+    let layout = args.layout();
+
     if let Some(expr) = &args.expr {
-        return match exec(expr, args.base, args.typ) {
+        return match exec(expr, &mut env, &args, &layout, false) {
             Ok(()) => ExitCode::SUCCESS,
             Err(()) => ExitCode::FAILURE,
         }
@@ -216,7 +887,11 @@ fn main() -> ExitCode {
                 if line.chars().all(|ch| ch.is_whitespace()) {
                     continue; 
                 }
-                let _ = exec(&line, args.base, args.typ);
+                if let Some(cmd) = line.trim_start().strip_prefix(':') {
+                    command(cmd, &env, &args, &layout);
+                    continue;
+                }
+                let _ = exec(&line, &mut env, &args, &layout, true);
             },
             Err(ReadlineError::Interrupted)| Err(ReadlineError::Eof) => break,
             Err(err) => println!("Error: {:?}", err),
@@ -231,11 +906,11 @@ mod tests {
     use std::fmt::Debug;
     use std::str::FromStr;
     use std::sync::LazyLock;
-    use std::io::BufWriter;
+    use std::io::{self, BufWriter};
 
-    use lalrpop_util::lalrpop_mod;
-    lalrpop_mod!(grammar, "/grammar.rs");
-    use super::{Base, write_int};
+    use super::{grammar, Base, BitOrder, Label, Layout, color_binary, ruler, write_int, write_labeled, write_pair, div_round_up, MAX_ROW_BITS};
+    use super::{group_digits, json_string, parse_error_span, write_json, write_json_error};
+    use crate::expr::{Env, Flags, IntType, Options, Value};
     use crate::traits::Int;
 
     use regex::Regex;
@@ -260,7 +935,7 @@ mod tests {
         }
 
         assert!(OVERALL_RE.with(|re| re.is_match(s)));
-        let digit_bits = T::from_u32(Base::Hex.bits()).unwrap();
+        let digit_bits = T::from_u32(Base::Hex.bits().unwrap()).unwrap();
         let mut val = T::zero();
         DIGIT_RE.with(|re| {
             for x in re.captures_iter(s) {
@@ -284,7 +959,7 @@ mod tests {
         // The first digit has fewer, but the first shift doesn't do anything
         // (the accumulator is all zeros), and after that the shift is by regular
         // 3-bit digits.
-        let digit_bits = T::from_u32(Base::Oct.bits()).unwrap();
+        let digit_bits = T::from_u32(Base::Oct.bits().unwrap()).unwrap();
 
         let mut val = T::zero();
         DIGIT_RE.with(|re| {
@@ -307,7 +982,7 @@ mod tests {
 
         assert!(OVERALL_RE.with(|re| re.is_match(s)));
         // See comment on digit_bits in check_oct()
-        let group_bits = T::from_u32(base.bits()).unwrap();
+        let group_bits = T::from_u32(base.bits().unwrap()).unwrap();
         let mut val = T::zero();
         DIGIT_RE.with(|re| {
             for x in re.captures_iter(s) {
@@ -326,34 +1001,49 @@ mod tests {
         let dec = lines.next().expect("Missing dec line");
         check_dec::<T>(dec, expected);
 
-        let hex_or_oct = lines.next().expect("Missing hex or oct line");
+        // Wide types are split into rows, each with a hex/oct and a bin line;
+        // join them back together.
+        let rows: Vec<_> = lines.collect();
+        let t_bits = T::zero().count_zeros();
+        assert_eq!(rows.len(), 2 * div_round_up(t_bits, MAX_ROW_BITS) as usize);
+        let join = |lines: Vec<&str>, subscript: &str| {
+            let digits: Vec<_> = lines.iter()
+                .map(|line| line.strip_suffix(subscript).expect("Missing subscript"))
+                .collect();
+            assert!(digits.iter().all(|row| !row.trim().is_empty()), "Blank row in {s:?}");
+            digits.join(" ") + subscript
+        };
+
+        let hex_or_oct = join(rows.iter().step_by(2).copied().collect(), &base.subscript());
         match base {
-            Base::Oct => check_oct::<T>(hex_or_oct, expected),
-            Base::Hex => check_hex::<T>(hex_or_oct, expected),
+            Base::Oct => check_oct::<T>(&hex_or_oct, expected),
+            Base::Hex => check_hex::<T>(&hex_or_oct, expected),
+            _ => unreachable!("Not aligned with binary: {base}"),
         }
 
-        let bin = lines.next().expect("Missing bin line");
-        check_bin::<T>(bin, base, expected);
+        let bin = join(rows.iter().skip(1).step_by(2).copied().collect(), "₂");
+        check_bin::<T>(&bin, base, expected);
+    }
 
-        assert_eq!(lines.next(), None);
+    /// What `write` writes.
+    fn render(write: impl FnOnce(&mut BufWriter<Vec<u8>>) -> io::Result<()>) -> String {
+        let mut output = BufWriter::new(vec![]);
+        write(&mut output).unwrap();
+        String::from_utf8(output.into_inner().unwrap()).unwrap()
     }
 
     fn run<T: Int + FromStr>(expr: &str, base: Base, expected: T)
         where <T as num_traits::Num>::FromStrRadixErr: Debug,
               <T as FromStr>::Err: Debug {
 
-        static PARSER: LazyLock<grammar::ExprParser> = LazyLock::new(||
-            Default::default()
-        );
+        static PARSER: LazyLock<grammar::ExprParser> = LazyLock::new(Default::default);
         
         let expr = PARSER.parse(expr).unwrap();
-        let val = expr.eval::<T>().unwrap();
+        let typ = IntType::new(T::is_signed(), T::zero().count_zeros());
+        let val = expr.eval_dyn(&Env::new(Options { default_type: typ, ..Default::default() })).unwrap().get::<T>();
         assert_eq!(val, expected);
 
-        let mut output = BufWriter::new(vec![]);
-        write_int(&mut output, val, base).unwrap();
-
-        let s = String::from_utf8(output.into_inner().unwrap()).unwrap();
+        let s = render(|f| write_int(f, val, T::zero().count_zeros(), &base.into(), None, None));
         check_output(&s, base, expected);
     }
 
@@ -366,6 +1056,283 @@ mod tests {
         run::<u32>("3 * 3", Base::Oct, 3 * 3);
     }
 
+    fn output<T: Int>(val: T, bits: u32, base: Base) -> String {
+        render(|f| write_int(f, val, bits, &base.into(), None, None))
+    }
+
+    #[test]
+    fn flags() {
+        let flags = Flags { n: true, z: false, c: true, v: false };
+        assert_eq!(render(|f| write_int(f, -2i8, 8, &Base::Hex.into(), None, Some(flags))), concat!(
+            "-2₁₀  [N=1 Z=0 C=1 V=0]\n",
+            "   F    E₁₆\n",
+            "1111 1110₂\n",
+        ));
+    }
+
+    #[test]
+    fn narrow() {
+        assert_eq!(output(0xabcu16, 12, Base::Hex), concat!(
+            "2748₁₀\n",
+            "   A    B    C₁₆\n",
+            "1010 1011 1100₂\n",
+        ));
+        assert_eq!(output(-2i16, 12, Base::Hex), concat!(
+            "-2₁₀\n",
+            "   F    F    E₁₆\n",
+            "1111 1111 1110₂\n",
+        ));
+        assert_eq!(output(0x123i16, 10, Base::Hex), concat!(
+            "291₁₀\n",
+            " 1    2    3₁₆\n",
+            "01 0010 0011₂\n",
+        ));
+        assert_eq!(output(-1i32, 24, Base::Oct), concat!(
+            "-1₁₀\n",
+            "  7   7   7   7   7   7   7   7₈\n",
+            "111 111 111 111 111 111 111 111₂\n",
+        ));
+        assert_eq!(output(1u8, 1, Base::Hex), concat!(
+            "1₁₀\n",
+            "1₁₆\n",
+            "1₂\n",
+        ));
+    }
+
+    #[test]
+    fn pair() {
+        fn pair<T: Int>(hi: T, lo: T, bits: u32) -> String {
+            render(|f| write_pair(f, hi, lo, bits, &Base::Hex.into(), None))
+        }
+
+        assert_eq!(pair(0x9cu8, 0x40u8, 8), concat!(
+            "156:64₁₀\n",
+            "   9    C :    4    0₁₆\n",
+            "1001 1100 : 0100 0000₂\n",
+        ));
+        assert_eq!(pair(-1i8, -2i8, 8), concat!(
+            "-1:-2₁₀\n",
+            "   F    F :    F    E₁₆\n",
+            "1111 1111 : 1111 1110₂\n",
+        ));
+        assert_eq!(pair(1u64, 2u64, 36), concat!(
+            "1:2₁₀\n",
+            "                                           1₁₆\n",
+            "0000 0000 0000 0000 0000 0000 0000 0000 0001₂\n",
+            "                                           2₁₆\n",
+            "0000 0000 0000 0000 0000 0000 0000 0000 0010₂\n",
+        ));
+    }
+
+    #[test]
+    fn show() {
+        fn show<T: Int>(val: T, bits: u32, show: &[Base]) -> String {
+            let layout = Layout { show: show.to_vec(), label: Label::Subscript, ruler: None, color: false };
+            render(|f| write_int(f, val, bits, &layout, None, None))
+        }
+        use Base::*;
+
+        assert_eq!(show(0xbeefu16, 16, &[Hex]), "BEEF₁₆\n");
+        assert_eq!(show(-1i16, 12, &[Dec]), "-1₁₀\n");
+        assert_eq!(show(-1i16, 12, &[Oct, Dec]), "7777₈\n-1₁₀\n");
+        assert_eq!(show(0xbeu8, 8, &[Bin, Hex]), concat!(
+            "1011 1110₂\n",
+            "   B    E₁₆\n",
+        ));
+        assert_eq!(show(0xbeu8, 8, &[Hex, Dec, Bin]), concat!(
+            "   B    E₁₆\n",
+            "190₁₀\n",
+            "1011 1110₂\n",
+        ));
+        assert_eq!(show(0xbeefu16, 16, &[Oct, Hex, Bin]), concat!(
+            "1   3   7   3   5   7₈\n",
+            "BEEF₁₆\n",
+            "1 011 111 011 101 111₂\n",
+        ));
+        assert_eq!(show(0x1_0000_0000_0000_0002u128, 68, &[Hex, Bin]), concat!(
+            "   1    0    0    0    0    0    0    0₁₆\n",
+            "0001 0000 0000 0000 0000 0000 0000 0000₂\n",
+            "   0    0    0    0    0    0    0    0    2₁₆\n",
+            "0000 0000 0000 0000 0000 0000 0000 0000 0010₂\n",
+        ));
+
+        let layout = Layout { show: vec![Bin, Dec], label: Label::Subscript, ruler: None, color: false };
+        let flags = Flags { n: false, z: false, c: true, v: false };
+        assert_eq!(render(|f| write_pair(f, 0x9cu8, 0x40u8, 8, &layout, Some(flags))), concat!(
+            "1001 1100 : 0100 0000₂  [N=0 Z=0 C=1 V=0]\n",
+            "156:64₁₀\n",
+        ));
+    }
+
+    #[test]
+    fn labeled() {
+        let layout = Layout { show: vec![Base::Hex, Base::Bin], label: Label::Subscript, ruler: None, color: false };
+        let text = render(|f| write_int(f, 0xbeefu16, 16, &layout, None, None));
+        assert_eq!(render(|f| write_labeled(f, "$12 = ", &text)), concat!(
+            "$12 =    B    E    E    F₁₆\n",
+            "      1011 1110 1110 1111₂\n",
+        ));
+        assert_eq!(render(|f| write_labeled(f, "", &text)), text);
+    }
+
+    #[test]
+    fn ascii() {
+        fn ascii<T: Int>(val: T, bits: u32, show: &[Base], label: Label) -> String {
+            render(|f| write_int(f, val, bits, &Layout { show: show.to_vec(), label, ruler: None, color: false }, None, None))
+        }
+        use Base::*;
+
+        assert_eq!(ascii(-2i8, 8, &[Dec, Hex, Bin], Label::Prefix), concat!(
+            "-2\n",
+            "0x   F    E\n",
+            "0b1111 1110\n",
+        ));
+        assert_eq!(ascii(-2i8, 8, &[Dec, Oct, Bin], Label::Suffix), concat!(
+            "-2d\n",
+            " 3   7   6o\n",
+            "11 111 110b\n",
+        ));
+        assert_eq!(ascii(0xb4u8, 8, &[Radix(4), Bin, Hex], Label::Prefix), concat!(
+            "0r4_ 2  3  1  0\n",
+            "  0b10 11 01 00\n",
+            "  0xB4\n",
+        ));
+        assert_eq!(ascii(0xb4u8, 8, &[Radix(4), Bin], Label::Suffix), concat!(
+            " 2  3  1  0_4\n",
+            "10 11 01 00b\n",
+        ));
+
+        let layout = Layout { show: vec![Hex, Bin], label: Label::Prefix, ruler: None, color: false };
+        assert_eq!(render(|f| write_pair(f, 0x9cu8, 0x40u8, 8, &layout, None)), concat!(
+            "0x   9    C :    4    0\n",
+            "0b1001 1100 : 0100 0000\n",
+        ));
+    }
+
+    #[test]
+    fn rulers() {
+        assert_eq!(ruler("0000 0101", 0, 8, BitOrder::Lsb0), "7    3  0");
+        assert_eq!(ruler("0000 0101", 0, 8, BitOrder::Msb0), "0    4  7");
+        assert_eq!(ruler("00 000 101", 0, 8, BitOrder::Lsb0), "7  5   2 0");
+        assert_eq!(ruler("0000 0000", 8, 16, BitOrder::Lsb0), "15   11 8");
+        assert_eq!(ruler("0000 0000", 8, 16, BitOrder::Msb0), "0    4  7");
+        assert_eq!(ruler("0000 0101", 0, 32, BitOrder::Msb0), "24     31");
+        assert_eq!(ruler("01 10", 0, 4, BitOrder::Lsb0), "3   0");
+        assert_eq!(ruler("1", 0, 1, BitOrder::Msb0), "0");
+
+        let layout = Layout { show: vec![Base::Hex, Base::Bin], label: Label::Subscript, ruler: Some(BitOrder::Lsb0), color: false };
+        assert_eq!(render(|f| write_pair(f, 0x9cu8, 0x40u8, 8, &layout, None)), concat!(
+            "   9    C :    4    0₁₆\n",
+            "1001 1100 : 0100 0000₂\n",
+            "7    3  0 : 7    3  0\n",
+        ));
+
+        let layout = Layout { show: vec![Base::Bin, Base::Oct], label: Label::Prefix, ruler: Some(BitOrder::Msb0), color: false };
+        assert_eq!(render(|f| write_int(f, 0x1_0000_0000_0000_0002u128, 68, &layout, None, None)), concat!(
+            "0b00 010 000 000 000 000 000 000 000 000 000\n",
+            "  0  2   5   8   11  14  17  20  23  26   31\n",
+            "0o     2   0   0   0   0   0   0   0   0   0\n",
+            "0b000 000 000 000 000 000 000 000 000 000 000 010\n",
+            "  32  35  38  41  44  47  50  53  56  59  62   67\n",
+            "0o  0   0   0   0   0   0   0   0   0   0   0   2\n",
+        ));
+    }
+
+    #[test]
+    fn color() {
+        assert_eq!(color_binary("0011 0", 4, 5, false, 0),
+            "\x1b[0;2m00\x1b[0;1m11\x1b[0m \x1b[0;2m0\x1b[0m");
+        assert_eq!(color_binary("10 0", 2, 3, true, 0),
+            "\x1b[0;1;31m1\x1b[0;2m0\x1b[0m \x1b[0;2m0\x1b[0m");
+        assert_eq!(color_binary("0011", 3, 4, false, 0b0110),
+            "\x1b[0;2m0\x1b[0;2;7m0\x1b[0;1;7m1\x1b[0;1m1\x1b[0m");
+        assert_eq!(color_binary("01", 65, 128, true, 1 << 65), "\x1b[0;2;7m0\x1b[0;1m1\x1b[0m");
+
+        let layout = Layout { show: vec![Base::Dec, Base::Bin], label: Label::Subscript, ruler: Some(BitOrder::Lsb0), color: true };
+        assert_eq!(render(|f| write_int(f, -3i8, 4, &layout, Some(-4i8), None)), concat!(
+            "-3₁₀\n",
+            "\x1b[0;1;31m1\x1b[0;1m1\x1b[0;2m0\x1b[0;1;7m1\x1b[0m₂\n",
+            "3  0\n",
+        ));
+    }
+
+    #[test]
+    fn radix() {
+        assert_eq!("36".parse(), Ok(Base::Radix(36)));
+        assert_eq!("16".parse(), Ok(Base::Hex));
+        assert_eq!("bin".parse(), Ok(Base::Bin));
+        assert!("37".parse::<Base>().is_err());
+        assert!("1".parse::<Base>().is_err());
+        assert_eq!(Base::Radix(32).subscript(), "₃₂");
+        assert_eq!(Base::Radix(36).digits(1295u16), "ZZ");
+        assert_eq!(Base::Radix(3).digits(0u8), "0");
+        assert_eq!(Base::Radix(7).digits(u128::MAX), "3115512162124626343001006330151620356026315303");
+
+        assert_eq!(output(0xdeadbeefu32, 32, Base::Radix(32)), concat!(
+            "3735928559₁₀\n",
+            " 3     F     A     R     F     N     F₃₂\n",
+            "11 01111 01010 11011 01111 10111 01111₂\n",
+        ));
+        assert_eq!(output(0xb4u8, 8, Base::Radix(4)), concat!(
+            "180₁₀\n",
+            " 2  3  1  0₄\n",
+            "10 11 01 00₂\n",
+        ));
+        assert_eq!(output(-1i8, 8, Base::Radix(36)), concat!(
+            "-1₁₀\n",
+            "73₃₆\n",
+            "1111 1111₂\n",
+        ));
+    }
+
+    #[test]
+    fn grouping() {
+        assert_eq!(group_digits("1011111011101111", 4), ["1011", "1110", "1110", "1111"]);
+        assert_eq!(group_digits("1234567", 3), ["1", "234", "567"]);
+        assert_eq!(group_digits("12", 3), ["12"]);
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(json_string("a\"b\\c\nd\te"), r#""a\"b\\c\nd\u0009e""#);
+
+        let json = |val: Value, n: Option<usize>, flags: Option<Flags>| {
+            render(|f| write_json(f, val, n, &Base::Radix(36).into(), flags))
+        };
+        assert_eq!(json(Value::new(IntType::new(true, 8), -2i8), Some(3), None), concat!(
+            r#"{"n": 3, "type": "i8", "value": "-2", "signed": "-2", "unsigned": "254", "#,
+            r#""bases": {"dec": "-2", "hex": "FE", "oct": "376", "bin": "11111110", "36": "72"}}"#, "\n",
+        ));
+        let flags = Flags { n: false, z: true, c: false, v: true };
+        assert_eq!(json(Value::pair(IntType::new(false, 8), 0x9cu8, 0x40u8), None, Some(flags)), concat!(
+            r#"{"type": "u8", "#,
+            r#""hi": {"value": "156", "signed": "-100", "unsigned": "156", "#,
+            r#""bases": {"dec": "156", "hex": "9C", "oct": "234", "bin": "10011100", "36": "4C"}}, "#,
+            r#""lo": {"value": "64", "signed": "64", "unsigned": "64", "#,
+            r#""bases": {"dec": "64", "hex": "40", "oct": "100", "bin": "1000000", "36": "1S"}}, "#,
+            r#""flags": {"n": false, "z": true, "c": false, "v": true}}"#, "\n",
+        ));
+
+        let error = |src: &str| {
+            let e = grammar::StmtParser::new().parse(src).unwrap_err();
+            render(|f| write_json_error(f, "parse", "msg", src, parse_error_span(src, &e)))
+        };
+        assert_eq!(error("'é' + 0b102"),
+            "{\"error\": {\"kind\": \"parse\", \"message\": \"msg\", \"span\": {\"start\": 6, \"end\": 11}}}\n");
+        assert_eq!(error("'é' $ 1"),
+            "{\"error\": {\"kind\": \"parse\", \"message\": \"msg\", \"span\": {\"start\": 4, \"end\": 5}}}\n");
+        assert_eq!(error("1 +"),
+            "{\"error\": {\"kind\": \"parse\", \"message\": \"msg\", \"span\": {\"start\": 3, \"end\": 3}}}\n");
+
+        let eval_error = |src: &str| {
+            let stmt = grammar::StmtParser::new().parse(src).unwrap();
+            let e = stmt.exec_flags(&mut Env::default()).unwrap_err();
+            render(|f| write_json_error(f, "eval", "msg", src, e.span()))
+        };
+        assert_eq!(eval_error("'é' + 1 / x"),
+            "{\"error\": {\"kind\": \"eval\", \"message\": \"msg\", \"span\": {\"start\": 10, \"end\": 11}}}\n");
+    }
+
     fn simple_tests<T: Int + FromStr>(base: Base)
         where <T as num_traits::Num>::FromStrRadixErr: Debug,
               <T as FromStr>::Err: Debug {
@@ -387,9 +1354,18 @@ mod tests {
         run("1 | 6 ^ 7 & 12", base, 3);
         run("(1 | 6) ^ 7 & 12", base, 3);
         run("1 | (6 ^ 7) & 12", base, 1);
-        run("!34", base, !34);
         run("~34", base, !34);
 
+        // `!` is logical NOT, as in C; bitwise NOT is only `~`.
+        run("!34", base, 0);
+        run("!0", base, 1);
+
+        run("2 < 3", base, 1);
+        run("2 >= 3", base, 0);
+        run("0 - 1 < 0", base, T::from_u8(T::is_signed() as u8).unwrap());
+        run("1 < 2 && 3 != 3 || 4 == 4", base, 1);
+        run("1 < 2 ? 10 : 20", base, 10);
+
         run("3 << 2", base, 12);
         run("12 >> 2", base, 3);
 
@@ -406,11 +1382,13 @@ mod tests {
             simple_tests::<u16>(base);
             simple_tests::<u32>(base);
             simple_tests::<u64>(base);
+            simple_tests::<u128>(base);
 
             simple_tests::<i8>(base);
             simple_tests::<i16>(base);
             simple_tests::<i32>(base);
             simple_tests::<i64>(base);
+            simple_tests::<i128>(base);
 
         }
     }
@@ -0,0 +1,270 @@
+//! Printf-style output templates, e.g. `0x%08X`.
+
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+use crate::{Base, group_digits};
+use crate::expr::{IntType, Value};
+
+/// What a directive writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Conv {
+    /// `d`: decimal, signed or unsigned according to the type.
+    #[default]
+    Dec,
+    /// `i`: decimal, interpreted as signed.
+    Signed,
+    /// `u`: decimal, interpreted as unsigned.
+    Unsigned,
+    /// `x`, `X`, `o`, or `b`: the bit pattern, in hex (lowercase or uppercase),
+    /// octal, or binary.
+    Digits(Base, bool),
+}
+
+/// A directive, e.g. `%#010x`, which writes the value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Directive {
+    /// `#`: prefix hex, octal, and binary with `0x` (or for `X`, `0X`), `0o`,
+    /// or `0b`.
+    prefix: bool,
+    /// `0`: pad with zeros, after any sign or prefix, rather than spaces.
+    zero: bool,
+    /// `-`: pad on the right, rather than the left.
+    left: bool,
+    /// `+`: write a `+` before non-negative signed decimal.
+    plus: bool,
+    /// `'`: separate digits with `_` into groups of 3 (for decimal and octal)
+    /// or 4 (for hex and binary), as in the output's chunks.
+    group: bool,
+    /// Minimum width, including any sign, prefix, and separators.
+    width: usize,
+    /// Minimum number of digits.
+    precision: usize,
+    conv: Conv,
+}
+
+impl Directive {
+    /// Writes `val`, which mustn't be a pair.
+    fn format(&self, val: Value) -> String {
+        let bits = val.get::<i128>();
+        let unsigned = IntType::new(false, val.typ.bits).wrap(bits as u128);
+        let signed = IntType::new(true, val.typ.bits).wrap(bits);
+
+        let (sign, base, digits) = match self.conv {
+            Conv::Dec if !val.typ.signed => ("", Base::Dec, unsigned.to_string()),
+            Conv::Unsigned => ("", Base::Dec, unsigned.to_string()),
+            Conv::Dec | Conv::Signed => {
+                let sign = match (signed < 0, self.plus) {
+                    (true, _) => "-",
+                    (false, true) => "+",
+                    (false, false) => "",
+                };
+                (sign, Base::Dec, signed.unsigned_abs().to_string())
+            }
+            Conv::Digits(base, upper) => {
+                let digits = base.digits(unsigned);
+                ("", base, if upper { digits } else { digits.to_lowercase() })
+            }
+        };
+        let prefix = match (self.prefix, self.conv) {
+            (true, Conv::Digits(Base::Hex, true)) => "0X",
+            (true, Conv::Digits(Base::Hex, false)) => "0x",
+            (true, Conv::Digits(Base::Oct, _)) => "0o",
+            (true, Conv::Digits(Base::Bin, _)) => "0b",
+            _ => "",
+        };
+
+        // Pad with zeros to the precision, then, for the `0` flag, to the
+        // width, counting the separators the zeros will be grouped with.
+        let grouped = |digits: &str| if self.group {
+            group_digits(digits, base.group_len()).join("_")
+        } else {
+            digits.to_owned()
+        };
+        let mut digits = format!("{digits:0>0$}", self.precision);
+        if self.zero && !self.left {
+            while sign.len() + prefix.len() + grouped(&digits).len() < self.width {
+                digits.insert(0, '0');
+            }
+        }
+        let body = format!("{sign}{prefix}{}", grouped(&digits));
+
+        let width = self.width;
+        if self.left {
+            format!("{body:<width$}")
+        } else {
+            format!("{body:>width$}")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Directive(Directive),
+}
+
+/// A template for printing values, of text with `%` directives, as with C's
+/// `printf` (see `Directive`). `%%` is a literal `%`, and `\n`, `\t`, and `\\`
+/// are escapes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Piece>);
+
+impl Template {
+    /// Writes `val`. For a pair, each directive writes both halves, as
+    /// `hi:lo`.
+    pub fn format(&self, val: Value) -> String {
+        let mut out = String::new();
+        for piece in &self.0 {
+            match piece {
+                Piece::Text(text) => out += text,
+                Piece::Directive(dir) => match val.hi() {
+                    Some(hi) => out += &format!("{}:{}", dir.format(hi), dir.format(val.lo())),
+                    None => out += &dir.format(val),
+                },
+            }
+        }
+        out
+    }
+}
+
+/// Parses a (decimal) number from the start of `chars`, or 0 if there isn't
+/// one.
+fn parse_number(chars: &mut Peekable<Chars>) -> usize {
+    let mut n = 0usize;
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        n = n.saturating_mul(10).saturating_add(digit.to_digit(10).unwrap() as usize);
+    }
+    n
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pieces = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' => text.push(match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('\\') => '\\',
+                    Some(ch) => return Err(format!("Unknown escape '\\{ch}'")),
+                    None => return Err("Unterminated escape".to_owned()),
+                }),
+                '%' if chars.peek() == Some(&'%') => {
+                    chars.next();
+                    text.push('%');
+                }
+                '%' => {
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+
+                    let mut dir = Directive::default();
+                    while let Some(flag) = chars.next_if(|ch| "#0-+'".contains(*ch)) {
+                        match flag {
+                            '#' => dir.prefix = true,
+                            '0' => dir.zero = true,
+                            '-' => dir.left = true,
+                            '+' => dir.plus = true,
+                            _ => dir.group = true,
+                        }
+                    }
+                    dir.width = parse_number(&mut chars);
+                    if chars.next_if_eq(&'.').is_some() {
+                        dir.precision = parse_number(&mut chars);
+                    }
+
+                    dir.conv = match chars.next() {
+                        Some('d') => Conv::Dec,
+                        Some('i') => Conv::Signed,
+                        Some('u') => Conv::Unsigned,
+                        Some('x') => Conv::Digits(Base::Hex, false),
+                        Some('X') => Conv::Digits(Base::Hex, true),
+                        Some('o') => Conv::Digits(Base::Oct, false),
+                        Some('b') => Conv::Digits(Base::Bin, false),
+                        Some(ch) => return Err(format!("Unknown conversion '%{ch}' (expected d, i, u, x, X, o, or b)")),
+                        None => return Err("Unterminated '%' directive".to_owned()),
+                    };
+                    pieces.push(Piece::Directive(dir));
+                }
+                ch => text.push(ch),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Template(pieces))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(template: &str, val: i128, typ: &str) -> String {
+        let typ: IntType = typ.parse().unwrap();
+        let template: Template = template.parse().unwrap();
+        template.format(Value::new(typ, val).cast(typ))
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(format("%d %i %u", -2, "i8"), "-2 -2 254");
+        assert_eq!(format("%d %i %u", 254, "u8"), "254 -2 254");
+        assert_eq!(format("%x %X %o %b", 0xbe, "u8"), "be BE 276 10111110");
+        assert_eq!(format("%x", -1, "i12"), "fff");
+        assert_eq!(format("%u", -1, "i128"), u128::MAX.to_string());
+        assert_eq!(format("%i", i128::MIN, "i128"), i128::MIN.to_string());
+        assert_eq!(format("%+d %+i %+u", 1, "i8"), "+1 +1 1");
+        assert_eq!(format("%%d = %d\\n\\t\\\\", 5, "u8"), "%d = 5\n\t\\");
+        assert_eq!(format("", 5, "u8"), "");
+    }
+
+    #[test]
+    fn padding() {
+        assert_eq!(format("0x%08X", 0xbeef, "u32"), "0x0000BEEF");
+        assert_eq!(format("%#010x", 0xbeef, "u32"), "0x0000beef");
+        assert_eq!(format("[%6d]", -42, "i32"), "[   -42]");
+        assert_eq!(format("[%-6d]", -42, "i32"), "[-42   ]");
+        assert_eq!(format("[%06d]", -42, "i32"), "[-00042]");
+        assert_eq!(format("[%-06d]", -42, "i32"), "[-42   ]");
+        assert_eq!(format("[%.4x]", 0xa, "u8"), "[000a]");
+        assert_eq!(format("[%8.4x]", 0xa, "u8"), "[    000a]");
+        assert_eq!(format("[%2x]", 0xabcd, "u16"), "[abcd]");
+        assert_eq!(format("%#o %#b %#X", 8, "u8"), "0o10 0b1000 0X8");
+    }
+
+    #[test]
+    fn grouping() {
+        assert_eq!(format("%'d", 1234567, "u32"), "1_234_567");
+        assert_eq!(format("%'d", -123456, "i32"), "-123_456");
+        assert_eq!(format("%'x", 0xdeadbeef, "u32"), "dead_beef");
+        assert_eq!(format("%'#b", 0b10110, "u8"), "0b1_0110");
+        assert_eq!(format("%'o", 0o1234, "u16"), "1_234");
+        assert_eq!(format("%'.8b", 0b10110, "u8"), "0001_0110");
+        assert_eq!(format("%'#011x", 0xbeef, "u32"), "0x0000_beef");
+        assert_eq!(format("%'#012x", 0xbeef, "u32"), "0x0_0000_beef");
+        assert_eq!(format("%'#.12o", -1, "u12"), "0o000_000_007_777");
+        assert_eq!(format("%'X", -1, "u65"), "1_FFFF_FFFF_FFFF_FFFF");
+    }
+
+    #[test]
+    fn pair() {
+        let template: Template = "0x%02x".parse().unwrap();
+        assert_eq!(template.format(Value::pair(IntType::new(false, 8), 0x9cu8, 0x4u8)), "0x9c:04");
+    }
+
+    #[test]
+    fn malformed() {
+        assert!("%".parse::<Template>().is_err());
+        assert!("%08".parse::<Template>().is_err());
+        assert!("%s".parse::<Template>().is_err());
+        assert!("\\q".parse::<Template>().is_err());
+        assert!("\\".parse::<Template>().is_err());
+    }
+}
@@ -3,6 +3,8 @@ use core::ops::{self, Div, Rem};
 use std::fmt;
 
 use num_traits::ops::wrapping::*;
+use num_traits::ops::checked::CheckedNeg;
+use num_traits::ops::saturating::SaturatingMul;
 use num_traits::cast::FromPrimitive;
 use num_traits::int::PrimInt;
 
@@ -54,6 +56,67 @@ wrapping_impl!(WrappingRem, wrapping_rem, isize);
 wrapping_impl!(WrappingRem, wrapping_rem, i128);
 
 
+macro_rules! saturating_impl {
+    ($trait_name:ident, $method:ident, $t:ty) => {
+        impl $trait_name for $t {
+            #[inline]
+            fn $method(&self, v: &Self) -> Self {
+                <$t>::$method(*self, *v)
+            }
+        }
+    };
+}
+
+
+pub trait SaturatingDiv: Sized + Div<Self, Output = Self> {
+    fn saturating_div(&self, v: &Self) -> Self;
+}
+
+saturating_impl!(SaturatingDiv, saturating_div, u8);
+saturating_impl!(SaturatingDiv, saturating_div, u16);
+saturating_impl!(SaturatingDiv, saturating_div, u32);
+saturating_impl!(SaturatingDiv, saturating_div, u64);
+saturating_impl!(SaturatingDiv, saturating_div, usize);
+saturating_impl!(SaturatingDiv, saturating_div, u128);
+saturating_impl!(SaturatingDiv, saturating_div, i8);
+saturating_impl!(SaturatingDiv, saturating_div, i16);
+saturating_impl!(SaturatingDiv, saturating_div, i32);
+saturating_impl!(SaturatingDiv, saturating_div, i64);
+saturating_impl!(SaturatingDiv, saturating_div, isize);
+saturating_impl!(SaturatingDiv, saturating_div, i128);
+
+
+/// Negation, saturating at the numeric bounds. For unsigned types, the result is
+/// always zero.
+pub trait SaturatingNeg: Sized {
+    fn saturating_neg(&self) -> Self;
+}
+
+macro_rules! saturating_neg_impl {
+    ($trait_name:ident, $t:ty) => {
+        impl $trait_name for $t {
+            #[inline]
+            fn saturating_neg(&self) -> Self {
+                <$t>::saturating_sub(0, *self)
+            }
+        }
+    };
+}
+
+saturating_neg_impl!(SaturatingNeg, u8);
+saturating_neg_impl!(SaturatingNeg, u16);
+saturating_neg_impl!(SaturatingNeg, u32);
+saturating_neg_impl!(SaturatingNeg, u64);
+saturating_neg_impl!(SaturatingNeg, usize);
+saturating_neg_impl!(SaturatingNeg, u128);
+saturating_neg_impl!(SaturatingNeg, i8);
+saturating_neg_impl!(SaturatingNeg, i16);
+saturating_neg_impl!(SaturatingNeg, i32);
+saturating_neg_impl!(SaturatingNeg, i64);
+saturating_neg_impl!(SaturatingNeg, isize);
+saturating_neg_impl!(SaturatingNeg, i128);
+
+
 pub trait AsUnsigned {
     type Unsigned: Int;
     fn as_unsigned(&self) -> Self::Unsigned;
@@ -82,6 +145,38 @@ convert_impl!(AsUnsigned, i64, u64);
 convert_impl!(AsUnsigned, i128, u128);
 
 
+/// Lossless round trip through i128, following the semantics of Rust's `as`: values
+/// are sign- or zero-extended on the way in, and truncated on the way out.
+pub trait AsI128 {
+    fn as_i128(&self) -> i128;
+    fn from_i128_as(v: i128) -> Self;
+}
+
+macro_rules! as_i128_impl {
+    ($trait_name:ident, $typ:ty) => {
+        impl AsI128 for $typ {
+            fn as_i128(&self) -> i128 {
+                *self as i128
+            }
+            fn from_i128_as(v: i128) -> Self {
+                v as Self
+            }
+        }
+    }
+}
+
+as_i128_impl!(AsI128, u8);
+as_i128_impl!(AsI128, u16);
+as_i128_impl!(AsI128, u32);
+as_i128_impl!(AsI128, u64);
+as_i128_impl!(AsI128, u128);
+as_i128_impl!(AsI128, i8);
+as_i128_impl!(AsI128, i16);
+as_i128_impl!(AsI128, i32);
+as_i128_impl!(AsI128, i64);
+as_i128_impl!(AsI128, i128);
+
+
 pub trait IsSigned {
     fn is_signed() -> bool;
 }
@@ -107,5 +202,5 @@ is_signed_impl!(IsSigned, i32, true);
 is_signed_impl!(IsSigned, i64, true);
 is_signed_impl!(IsSigned, i128, true);
 
-pub trait Int = PrimInt + WrappingAdd + WrappingSub + WrappingMul + WrappingNeg + WrappingShl + WrappingShr + WrappingDiv + WrappingRem + ops::AddAssign + FromPrimitive + fmt::Display + fmt::Debug + fmt::Octal + fmt::UpperHex + fmt::Binary + ops::ShrAssign + ops::ShlAssign + AsUnsigned + IsSigned;
+pub trait Int = PrimInt + WrappingAdd + WrappingSub + WrappingMul + WrappingNeg + WrappingShl + WrappingShr + WrappingDiv + WrappingRem + CheckedNeg + SaturatingMul + SaturatingDiv + SaturatingNeg + ops::AddAssign + FromPrimitive + fmt::Display + fmt::Debug + fmt::Octal + fmt::UpperHex + fmt::Binary + ops::ShrAssign + ops::ShlAssign + AsUnsigned + AsI128 + IsSigned;
 
@@ -0,0 +1,162 @@
+//! Double-width multiplication and division, for types up to 128 bits.
+//!
+//! Values are bit patterns `bits` wide, held in the low bits of a u128, and are
+//! interpreted as signed or unsigned as the functions specify.
+
+#[inline]
+fn mask(bits: u32) -> u128 {
+    u128::MAX >> (128 - bits)
+}
+
+/// Whether `val`, `bits` wide, is negative when interpreted as signed.
+#[inline]
+fn is_neg(val: u128, bits: u32) -> bool {
+    (val >> (bits - 1)) & 1 != 0
+}
+
+#[inline]
+fn neg(val: u128, bits: u32) -> u128 {
+    val.wrapping_neg() & mask(bits)
+}
+
+/// Full 256-bit product of `a` and `b`, as (high, low).
+fn mul_u128(a: u128, b: u128) -> (u128, u128) {
+    const HALF: u32 = 64;
+    let lo_mask = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> HALF, a & lo_mask);
+    let (b_hi, b_lo) = (b >> HALF, b & lo_mask);
+
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_hi = a_hi * b_hi;
+
+    // Sum the middle terms along with the carry out of the low term; this
+    // can't overflow, as each part is less than 2^128 - 2^65 + 1.
+    let mid = (lo_lo >> HALF) + (hi_lo & lo_mask) + (lo_hi & lo_mask);
+    let lo = (mid << HALF) | (lo_lo & lo_mask);
+    let hi = hi_hi + (hi_lo >> HALF) + (lo_hi >> HALF) + (mid >> HALF);
+    (hi, lo)
+}
+
+/// Full product of `a` and `b`, as (high, low) halves each `bits` wide. Each
+/// operand is interpreted as signed if the corresponding flag is set.
+pub fn mul(a: u128, b: u128, bits: u32, a_signed: bool, b_signed: bool) -> (u128, u128) {
+    let (prod_hi, prod_lo) = mul_u128(a, b);
+    let lo = prod_lo & mask(bits);
+    let mut hi = if bits == 128 {
+        prod_hi
+    } else {
+        ((prod_hi << (128 - bits)) | (prod_lo >> bits)) & mask(bits)
+    };
+
+    // The unsigned product of two's complement values differs from the signed
+    // product by the other operand, shifted up by `bits`, for each negative
+    // operand.
+    if a_signed && is_neg(a, bits) {
+        hi = hi.wrapping_sub(b) & mask(bits);
+    }
+    if b_signed && is_neg(b, bits) {
+        hi = hi.wrapping_sub(a) & mask(bits);
+    }
+    (hi, lo)
+}
+
+/// Unsigned division of `hi:lo` by `d`, as (quotient, remainder), or None if
+/// the quotient doesn't fit in `bits`. `d` must be nonzero.
+fn div_unsigned(hi: u128, lo: u128, d: u128, bits: u32) -> Option<(u128, u128)> {
+    if hi >= d {
+        return None;
+    }
+
+    // Shift-and-subtract long division. The remainder is always less than `d`,
+    // but shifting it may carry out of `bits`.
+    let mut rem = hi;
+    let mut quot = 0;
+    for i in (0..bits).rev() {
+        let carry = is_neg(rem, bits);
+        rem = ((rem << 1) & mask(bits)) | ((lo >> i) & 1);
+        if carry || rem >= d {
+            rem = rem.wrapping_sub(d) & mask(bits);
+            quot |= 1 << i;
+        }
+    }
+    Some((quot, rem))
+}
+
+/// Division of `hi:lo` by `d`, as (quotient, remainder), or None if the
+/// quotient doesn't fit in `bits`. Like `/` and `%`, rounds towards zero. `d`
+/// must be nonzero.
+pub fn div(hi: u128, lo: u128, d: u128, bits: u32, signed: bool) -> Option<(u128, u128)> {
+    if !signed {
+        return div_unsigned(hi, lo, d, bits);
+    }
+
+    // Divide the magnitudes, then fix up the signs.
+    let dividend_neg = is_neg(hi, bits);
+    let (hi_mag, lo_mag) = if dividend_neg {
+        let borrow = (lo == 0) as u128;
+        ((!hi).wrapping_add(borrow) & mask(bits), neg(lo, bits))
+    } else {
+        (hi, lo)
+    };
+    let d_neg = is_neg(d, bits);
+    let d_mag = if d_neg { neg(d, bits) } else { d };
+
+    let (quot, rem) = div_unsigned(hi_mag, lo_mag, d_mag, bits)?;
+    let quot_neg = dividend_neg != d_neg;
+    let quot_max = 1 << (bits - 1);
+    if quot > quot_max || (quot == quot_max && !quot_neg) {
+        return None;
+    }
+
+    Some((
+        if quot_neg { neg(quot, bits) } else { quot },
+        if dividend_neg { neg(rem, bits) } else { rem },
+    ))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_full() {
+        assert_eq!(mul(200, 200, 8, false, false), (0x9c, 0x40));
+        assert_eq!(mul(0xff, 0xff, 8, true, true), (0, 1));
+        assert_eq!(mul(0xff, 2, 8, true, true), (0xff, 0xfe));
+        assert_eq!(mul(0xff, 0xff, 8, true, false), (0xff, 0x01));
+        assert_eq!(mul(0x80, 0x80, 8, true, true), (0x40, 0));
+        assert_eq!(mul(0xfff, 0xfff, 12, false, false), (0xffe, 0x001));
+
+        let (hi, lo) = mul(u64::MAX as u128, u64::MAX as u128, 64, false, false);
+        assert_eq!((hi << 64) | lo, (u64::MAX as u128) * (u64::MAX as u128));
+
+        assert_eq!(mul(u128::MAX, u128::MAX, 128, false, false), (u128::MAX - 1, 1));
+        assert_eq!(mul(u128::MAX, u128::MAX, 128, true, true), (0, 1));
+        assert_eq!(mul(1 << 127, 2, 128, false, false), (1, 0));
+        assert_eq!(mul(1 << 127, 2, 128, true, true), (u128::MAX, 0));
+    }
+
+    #[test]
+    fn div_full() {
+        assert_eq!(div(0x9c, 0x40, 200, 8, false), Some((200, 0)));
+        assert_eq!(div(0x9c, 0x41, 200, 8, false), Some((200, 1)));
+        assert_eq!(div(1, 0, 1, 8, false), None);
+        assert_eq!(div(0, 7, 2, 8, false), Some((3, 1)));
+
+        // -7 / 2 = -3 rem -1.
+        assert_eq!(div(0xff, 0xf9, 2, 8, true), Some((0xfd, 0xff)));
+        // 7 / -2 = -3 rem 1.
+        assert_eq!(div(0, 7, 0xfe, 8, true), Some((0xfd, 1)));
+        // -256 / -2 = 128, which overflows.
+        assert_eq!(div(0xff, 0, 0xfe, 8, true), None);
+        // -256 / 2 = -128, which doesn't.
+        assert_eq!(div(0xff, 0, 2, 8, true), Some((0x80, 0)));
+
+        assert_eq!(div(u128::MAX - 1, 1, u128::MAX, 128, false), Some((u128::MAX, 0)));
+        assert_eq!(div(0, u128::MAX, 1 << 127, 128, false), Some((1, (1 << 127) - 1)));
+        assert_eq!(div(u128::MAX, u128::MAX, u128::MAX, 128, true), Some((1, 0)));
+    }
+}